@@ -0,0 +1,87 @@
+// Physically based bloom: the bright parts of the scene are blurred through a
+// chain of ever smaller mips and added back on top of the scene.
+#include "fullscreen.wgsl"
+
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    radius: f32,
+}
+
+@group(0) @binding(0)
+var t_input: texture_2d<f32>;
+@group(0) @binding(1)
+var s_input: sampler;
+
+@group(1) @binding(0)
+var<uniform> bloom: BloomUniform;
+
+// The blurred bloom, only used when compositing.
+@group(2) @binding(0)
+var t_bloom: texture_2d<f32>;
+@group(2) @binding(1)
+var s_bloom: sampler;
+
+// 13 tap filter from Jimenez's "Next Generation Post Processing in Call of Duty:
+// Advanced Warfare", halving the resolution without flickering.
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    let t = 1.0 / vec2<f32>(textureDimensions(t_input));
+    let a = textureSample(t_input, s_input, uv + t * vec2<f32>(-2.0, -2.0)).rgb;
+    let b = textureSample(t_input, s_input, uv + t * vec2<f32>(0.0, -2.0)).rgb;
+    let c = textureSample(t_input, s_input, uv + t * vec2<f32>(2.0, -2.0)).rgb;
+    let d = textureSample(t_input, s_input, uv + t * vec2<f32>(-2.0, 0.0)).rgb;
+    let e = textureSample(t_input, s_input, uv).rgb;
+    let f = textureSample(t_input, s_input, uv + t * vec2<f32>(2.0, 0.0)).rgb;
+    let g = textureSample(t_input, s_input, uv + t * vec2<f32>(-2.0, 2.0)).rgb;
+    let h = textureSample(t_input, s_input, uv + t * vec2<f32>(0.0, 2.0)).rgb;
+    let i = textureSample(t_input, s_input, uv + t * vec2<f32>(2.0, 2.0)).rgb;
+    let j = textureSample(t_input, s_input, uv + t * vec2<f32>(-1.0, -1.0)).rgb;
+    let k = textureSample(t_input, s_input, uv + t * vec2<f32>(1.0, -1.0)).rgb;
+    let l = textureSample(t_input, s_input, uv + t * vec2<f32>(-1.0, 1.0)).rgb;
+    let m = textureSample(t_input, s_input, uv + t * vec2<f32>(1.0, 1.0)).rgb;
+    return e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625 + (j + k + l + m) * 0.125;
+}
+
+// Keeps what is brighter than the threshold, with a soft knee below it so the
+// bloom doesn't start abruptly.
+fn prefilter(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    var soft = clamp(brightness - bloom.threshold + bloom.knee, 0.0, 2.0 * bloom.knee);
+    soft = soft * soft / (4.0 * bloom.knee + 0.00001);
+    let contribution = max(soft, brightness - bloom.threshold) / max(brightness, 0.00001);
+    return color * contribution;
+}
+
+@fragment
+fn fs_prefilter(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(prefilter(downsample(in.uv)), 1.0);
+}
+
+@fragment
+fn fs_downsample(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample(in.uv), 1.0);
+}
+
+// 3x3 tent filter, blended additively onto the larger mip.
+@fragment
+fn fs_upsample(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let t = bloom.radius / vec2<f32>(textureDimensions(t_input));
+    var sum = textureSample(t_input, s_input, in.uv).rgb * 4.0;
+    sum += textureSample(t_input, s_input, in.uv + t * vec2<f32>(0.0, -1.0)).rgb * 2.0;
+    sum += textureSample(t_input, s_input, in.uv + t * vec2<f32>(-1.0, 0.0)).rgb * 2.0;
+    sum += textureSample(t_input, s_input, in.uv + t * vec2<f32>(1.0, 0.0)).rgb * 2.0;
+    sum += textureSample(t_input, s_input, in.uv + t * vec2<f32>(0.0, 1.0)).rgb * 2.0;
+    sum += textureSample(t_input, s_input, in.uv + t * vec2<f32>(-1.0, -1.0)).rgb;
+    sum += textureSample(t_input, s_input, in.uv + t * vec2<f32>(1.0, -1.0)).rgb;
+    sum += textureSample(t_input, s_input, in.uv + t * vec2<f32>(-1.0, 1.0)).rgb;
+    sum += textureSample(t_input, s_input, in.uv + t * vec2<f32>(1.0, 1.0)).rgb;
+    return vec4<f32>(sum / 16.0, 1.0);
+}
+
+@fragment
+fn fs_composite(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let scene = textureSample(t_input, s_input, in.uv);
+    let glow = textureSample(t_bloom, s_bloom, in.uv).rgb;
+    return vec4<f32>(scene.rgb + glow * bloom.intensity, scene.a);
+}
@@ -0,0 +1,10 @@
+// Camera shared by every pass, bound to group 1.
+struct CameraUniform {
+    view_proj: mat4x4<f32>,
+    // From clip space back to world space.
+    inverse_view_proj: mat4x4<f32>,
+    // World space, w is unused.
+    view_position: vec4<f32>,
+};
+@group(1) @binding(0) // 1.
+var<uniform> camera: CameraUniform;
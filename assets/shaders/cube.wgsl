@@ -0,0 +1,17 @@
+// Cubemap faces in layer order: +X, -X, +Y, -Y, +Z, -Z.
+
+// Unit direction through `uv` of cube face `face`, uv going from (0, 0) in the
+// top left corner of the face to (1, 1) in the bottom right.
+fn cube_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    let st = uv * 2.0 - 1.0;
+    var direction: vec3<f32>;
+    switch face {
+        case 0u: { direction = vec3<f32>(1.0, -st.y, -st.x); }
+        case 1u: { direction = vec3<f32>(-1.0, -st.y, st.x); }
+        case 2u: { direction = vec3<f32>(st.x, 1.0, st.y); }
+        case 3u: { direction = vec3<f32>(st.x, -1.0, -st.y); }
+        case 4u: { direction = vec3<f32>(st.x, -st.y, 1.0); }
+        default: { direction = vec3<f32>(-st.x, -st.y, -1.0); }
+    }
+    return normalize(direction);
+}
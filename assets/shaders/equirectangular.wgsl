@@ -0,0 +1,37 @@
+// Renders one face of a cubemap from an equirectangular panorama.
+#include "fullscreen.wgsl"
+#include "cube.wgsl"
+
+const PI: f32 = 3.14159265359;
+
+@group(0) @binding(0)
+var t_panorama: texture_2d<f32>;
+
+struct FaceUniform {
+    face: u32,
+}
+@group(1) @binding(0)
+var<uniform> face: FaceUniform;
+
+fn texel(coords: vec2<i32>, size: vec2<i32>) -> vec4<f32> {
+    // Wraps around horizontally, the panorama is continuous there.
+    let x = (coords.x % size.x + size.x) % size.x;
+    let y = clamp(coords.y, 0, size.y - 1);
+    return textureLoad(t_panorama, vec2<i32>(x, y), 0);
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let direction = cube_direction(face.face, in.uv);
+    let uv = vec2<f32>(atan2(direction.z, direction.x) / (2.0 * PI) + 0.5, acos(direction.y) / PI);
+
+    // 32 bit float textures can't be filtered by the sampler, so this is
+    // bilinear filtering by hand.
+    let size = vec2<i32>(textureDimensions(t_panorama));
+    let position = uv * vec2<f32>(size) - 0.5;
+    let base = vec2<i32>(floor(position));
+    let t = fract(position);
+    let top = mix(texel(base, size), texel(base + vec2<i32>(1, 0), size), t.x);
+    let bottom = mix(texel(base + vec2<i32>(0, 1), size), texel(base + vec2<i32>(1, 1), size), t.x);
+    return vec4<f32>(mix(top, bottom, t.y).rgb, 1.0);
+}
@@ -0,0 +1,73 @@
+// Fog shared by the scene shader and the sky, see fog.rs. Including shaders
+// declare the uniform in their own bind group and pass it in.
+
+// Matches FogUniform::from in fog.rs.
+const FOG_OFF: u32 = 0u;
+const FOG_LINEAR: u32 = 1u;
+const FOG_EXPONENTIAL: u32 = 2u;
+const FOG_HEIGHT: u32 = 3u;
+
+struct FogUniform {
+    color: vec4<f32>,
+    mode: u32,
+    density: f32,
+    start: f32,
+    end: f32,
+    height: f32,
+    falloff: f32,
+}
+
+// How much of the light from `world_position` is replaced by fog on its way
+// to the camera, from 0 to 1.
+fn fog_amount(fog: FogUniform, world_position: vec3<f32>, view_offset: vec3<f32>) -> f32 {
+    let distance = length(view_offset);
+    switch fog.mode {
+        case FOG_LINEAR: {
+            return saturate((distance - fog.start) / max(fog.end - fog.start, 1e-4));
+        }
+        case FOG_EXPONENTIAL: {
+            return 1.0 - exp(-fog.density * distance);
+        }
+        case FOG_HEIGHT: {
+            // The density falls off exponentially above fog.height. This is its
+            // integral along the ray from the camera.
+            let camera_height = world_position.y - view_offset.y;
+            let start_density = fog.density * exp(-fog.falloff * (camera_height - fog.height));
+            let rise = fog.falloff * view_offset.y;
+            var thinning = 1.0;
+            if abs(rise) > 1e-4 {
+                thinning = (1.0 - exp(-rise)) / rise;
+            }
+            return 1.0 - exp(-start_density * distance * thinning);
+        }
+        default: {
+            return 0.0;
+        }
+    }
+}
+
+// fog_amount for something infinitely far away in `direction`, like the sky.
+fn sky_fog_amount(fog: FogUniform, camera_height: f32, direction: vec3<f32>) -> f32 {
+    let thick = select(0.0, 1.0, fog.density > 0.0);
+    switch fog.mode {
+        case FOG_LINEAR: {
+            return 1.0;
+        }
+        case FOG_EXPONENTIAL: {
+            return thick;
+        }
+        case FOG_HEIGHT: {
+            // Only rays going up leave the fog, through less of it the steeper
+            // they rise.
+            let rise = fog.falloff * normalize(direction).y;
+            if rise <= 0.0 {
+                return thick;
+            }
+            let start_density = fog.density * exp(-fog.falloff * (camera_height - fog.height));
+            return 1.0 - exp(-start_density / rise);
+        }
+        default: {
+            return 0.0;
+        }
+    }
+}
@@ -0,0 +1,17 @@
+// Vertex shader for passes that process the whole screen. Draw it with 3
+// vertices and no vertex buffers: the triangle covers the screen, with uv going
+// from (0, 0) in the top left corner to (1, 1) in the bottom right.
+
+struct FullscreenOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: FullscreenOutput;
+    out.clip_position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
@@ -0,0 +1,171 @@
+// FXAA 3.11 quality, after Timothy Lottes: finds the edge through each pixel
+// from the contrast of its neighbours, walks along it to both ends and blends
+// the pixel across the edge by how close it is to an end.
+#include "fullscreen.wgsl"
+
+// Contrast below which pixels are left alone, absolute and relative to the
+// brightest neighbour.
+const EDGE_THRESHOLD_MIN: f32 = 0.0312;
+const EDGE_THRESHOLD_MAX: f32 = 0.125;
+// Steps taken along an edge in each direction looking for its end.
+const SEARCH_STEPS: u32 = 12u;
+// How much single pixel features are smoothed, 0 keeps them sharp.
+const SUBPIXEL_QUALITY: f32 = 0.75;
+
+@group(0) @binding(0)
+var t_input: texture_2d<f32>;
+@group(0) @binding(1)
+var s_input: sampler;
+
+// Luma with roughly perceptual spacing, which the thresholds are tuned for.
+fn luma(color: vec3<f32>) -> f32 {
+    let luma = dot(color, vec3<f32>(0.299, 0.587, 0.114));
+#ifdef LINEAR_INPUT
+    // sRGB targets are read back linear.
+    return sqrt(luma);
+#else
+    return luma;
+#endif
+}
+
+// Sampled explicitly from the top level, derivatives aren't available in the
+// non-uniform control flow below.
+fn luma_at(uv: vec2<f32>) -> f32 {
+    return luma(textureSampleLevel(t_input, s_input, uv, 0.0).rgb);
+}
+
+// The search speeds up the further it goes.
+fn search_step(i: u32) -> f32 {
+    if i < 5u {
+        return 1.0;
+    } else if i == 5u {
+        return 1.5;
+    } else if i < 10u {
+        return 2.0;
+    } else if i == 10u {
+        return 4.0;
+    }
+    return 8.0;
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_input));
+    let uv = in.uv;
+    let center = textureSampleLevel(t_input, s_input, uv, 0.0);
+
+    let luma_center = luma(center.rgb);
+    let luma_up = luma_at(uv + vec2<f32>(0.0, -texel.y));
+    let luma_down = luma_at(uv + vec2<f32>(0.0, texel.y));
+    let luma_left = luma_at(uv + vec2<f32>(-texel.x, 0.0));
+    let luma_right = luma_at(uv + vec2<f32>(texel.x, 0.0));
+
+    let luma_min = min(luma_center, min(min(luma_up, luma_down), min(luma_left, luma_right)));
+    let luma_max = max(luma_center, max(max(luma_up, luma_down), max(luma_left, luma_right)));
+    let range = luma_max - luma_min;
+    if range < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD_MAX) {
+        return center;
+    }
+
+    let luma_up_left = luma_at(uv - texel);
+    let luma_down_right = luma_at(uv + texel);
+    let luma_up_right = luma_at(uv + vec2<f32>(texel.x, -texel.y));
+    let luma_down_left = luma_at(uv + vec2<f32>(-texel.x, texel.y));
+
+    let luma_vertical = luma_up + luma_down;
+    let luma_horizontal = luma_left + luma_right;
+    let luma_left_corners = luma_up_left + luma_down_left;
+    let luma_right_corners = luma_up_right + luma_down_right;
+    let luma_up_corners = luma_up_left + luma_up_right;
+    let luma_down_corners = luma_down_left + luma_down_right;
+
+    // Whether the edge runs horizontally, i.e. the contrast is vertical.
+    let edge_horizontal = abs(-2.0 * luma_left + luma_left_corners)
+        + abs(-2.0 * luma_center + luma_vertical) * 2.0
+        + abs(-2.0 * luma_right + luma_right_corners);
+    let edge_vertical = abs(-2.0 * luma_up + luma_up_corners)
+        + abs(-2.0 * luma_center + luma_horizontal) * 2.0
+        + abs(-2.0 * luma_down + luma_down_corners);
+    let is_horizontal = edge_horizontal >= edge_vertical;
+
+    // Which side of the pixel the edge is on.
+    let luma_negative = select(luma_left, luma_up, is_horizontal);
+    let luma_positive = select(luma_right, luma_down, is_horizontal);
+    let gradient_negative = luma_negative - luma_center;
+    let gradient_positive = luma_positive - luma_center;
+    let negative_steepest = abs(gradient_negative) >= abs(gradient_positive);
+    let gradient_scaled = 0.25 * max(abs(gradient_negative), abs(gradient_positive));
+
+    var step_length = select(texel.x, texel.y, is_horizontal);
+    var luma_local_average: f32;
+    if negative_steepest {
+        step_length = -step_length;
+        luma_local_average = 0.5 * (luma_negative + luma_center);
+    } else {
+        luma_local_average = 0.5 * (luma_positive + luma_center);
+    }
+
+    // Walk along the edge, half a texel over so both sides are sampled at once.
+    var edge_uv = uv;
+    if is_horizontal {
+        edge_uv.y += step_length * 0.5;
+    } else {
+        edge_uv.x += step_length * 0.5;
+    }
+    let offset = select(vec2<f32>(0.0, texel.y), vec2<f32>(texel.x, 0.0), is_horizontal);
+
+    var uv_negative = edge_uv - offset;
+    var uv_positive = edge_uv + offset;
+    var luma_end_negative = 0.0;
+    var luma_end_positive = 0.0;
+    var reached_negative = false;
+    var reached_positive = false;
+    for (var i = 0u; i < SEARCH_STEPS; i++) {
+        if !reached_negative {
+            luma_end_negative = luma_at(uv_negative) - luma_local_average;
+            reached_negative = abs(luma_end_negative) >= gradient_scaled;
+        }
+        if !reached_positive {
+            luma_end_positive = luma_at(uv_positive) - luma_local_average;
+            reached_positive = abs(luma_end_positive) >= gradient_scaled;
+        }
+        if reached_negative && reached_positive {
+            break;
+        }
+        if !reached_negative {
+            uv_negative -= offset * search_step(i + 1u);
+        }
+        if !reached_positive {
+            uv_positive += offset * search_step(i + 1u);
+        }
+    }
+
+    let distance_negative = select(uv.y - uv_negative.y, uv.x - uv_negative.x, is_horizontal);
+    let distance_positive = select(uv_positive.y - uv.y, uv_positive.x - uv.x, is_horizontal);
+    let negative_closer = distance_negative < distance_positive;
+    let distance = min(distance_negative, distance_positive);
+    let edge_length = distance_negative + distance_positive;
+
+    // Only blend if the end closer by goes the same way as the pixel, otherwise
+    // the pixel is on the far side of the edge.
+    let luma_end = select(luma_end_positive, luma_end_negative, negative_closer);
+    let center_smaller = luma_center < luma_local_average;
+    var pixel_offset = 0.0;
+    if (luma_end < 0.0) != center_smaller {
+        pixel_offset = 0.5 - distance / edge_length;
+    }
+
+    // Pixels unlike all their neighbours are smoothed regardless of edges.
+    let luma_average = (2.0 * (luma_vertical + luma_horizontal) + luma_left_corners + luma_right_corners) / 12.0;
+    let subpixel = saturate(abs(luma_average - luma_center) / range);
+    let subpixel_smooth = (-2.0 * subpixel + 3.0) * subpixel * subpixel;
+    pixel_offset = max(pixel_offset, subpixel_smooth * subpixel_smooth * SUBPIXEL_QUALITY);
+
+    var final_uv = uv;
+    if is_horizontal {
+        final_uv.y += pixel_offset * step_length;
+    } else {
+        final_uv.x += pixel_offset * step_length;
+    }
+    return vec4<f32>(textureSampleLevel(t_input, s_input, final_uv, 0.0).rgb, center.a);
+}
@@ -0,0 +1,143 @@
+// Precomputes image-based lighting from an environment cubemap: the diffuse
+// irradiance, the specular reflection prefiltered for increasing roughness and
+// the lookup table of the split-sum BRDF approximation, after Brian Karis'
+// "Real Shading in Unreal Engine 4".
+#include "fullscreen.wgsl"
+#include "cube.wgsl"
+#include "pbr.wgsl"
+
+const IRRADIANCE_SAMPLES: u32 = 1024u;
+const PREFILTER_SAMPLES: u32 = 512u;
+const BRDF_SAMPLES: u32 = 512u;
+
+@group(0) @binding(0)
+var t_environment: texture_cube<f32>;
+@group(0) @binding(1)
+var s_environment: sampler;
+
+// Matches IblUniform in ibl.rs.
+struct IblUniform {
+    face: u32,
+    roughness: f32,
+}
+@group(1) @binding(0)
+var<uniform> pass_info: IblUniform;
+
+// Evenly spread points in the unit square.
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), f32(reverseBits(i)) / 4294967296.0);
+}
+
+// Rotates `v`, given around +z, to be around `normal`.
+fn to_world(v: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    var up = vec3<f32>(0.0, 0.0, 1.0);
+    if abs(normal.z) > 0.999 {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+    return tangent * v.x + bitangent * v.y + normal * v.z;
+}
+
+// A half vector around +z, distributed like the GGX microfacet normals.
+fn importance_sample_ggx(xi: vec2<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+}
+
+// The mip level of the environment whose texels cover about the solid angle a
+// sample with probability `pdf` stands for. Reading it instead of the top level
+// keeps a few samples from showing up as bright speckles.
+fn sample_level(pdf: f32, samples: u32) -> f32 {
+    let size = f32(textureDimensions(t_environment).x);
+    let texel_angle = 4.0 * PI / (6.0 * size * size);
+    let sample_angle = 1.0 / (f32(samples) * pdf + 1e-4);
+    return max(0.5 * log2(sample_angle / texel_angle), 0.0);
+}
+
+// Renders a face of the next mip level from the one bound, the sampler
+// averaging four texels into each.
+@fragment
+fn fs_downsample(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let direction = cube_direction(pass_info.face, in.uv);
+    return vec4<f32>(textureSampleLevel(t_environment, s_environment, direction, 0.0).rgb, 1.0);
+}
+
+// The light arriving at a surface facing each direction, cosine weighted and
+// divided by pi so multiplying it with the albedo gives the diffuse reflection.
+@fragment
+fn fs_irradiance(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let normal = cube_direction(pass_info.face, in.uv);
+    var irradiance = vec3<f32>(0.0);
+    for (var i = 0u; i < IRRADIANCE_SAMPLES; i++) {
+        // Cosine weighted, so the weights cancel out.
+        let xi = hammersley(i, IRRADIANCE_SAMPLES);
+        let r = sqrt(xi.y);
+        let phi = 2.0 * PI * xi.x;
+        let local = vec3<f32>(r * cos(phi), r * sin(phi), sqrt(1.0 - xi.y));
+        let pdf = local.z / PI;
+        let level = sample_level(pdf, IRRADIANCE_SAMPLES) + 1.0;
+        irradiance += textureSampleLevel(t_environment, s_environment, to_world(local, normal), level).rgb;
+    }
+    return vec4<f32>(irradiance / f32(IRRADIANCE_SAMPLES), 1.0);
+}
+
+// The environment as reflected by a surface of the given roughness, taking the
+// view to be along the normal.
+@fragment
+fn fs_prefilter(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let normal = cube_direction(pass_info.face, in.uv);
+    let roughness = pass_info.roughness;
+    if roughness == 0.0 {
+        return vec4<f32>(textureSampleLevel(t_environment, s_environment, normal, 0.0).rgb, 1.0);
+    }
+
+    var color = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < PREFILTER_SAMPLES; i++) {
+        let half_vector = to_world(importance_sample_ggx(hammersley(i, PREFILTER_SAMPLES), roughness), normal);
+        let light = reflect(-normal, half_vector);
+        let n_dot_l = dot(normal, light);
+        if n_dot_l > 0.0 {
+            // With the view along the normal, n.h and v.h are the same.
+            let n_dot_h = saturate(dot(normal, half_vector));
+            let pdf = distribution_ggx(n_dot_h, roughness) / 4.0 + 1e-4;
+            let level = sample_level(pdf, PREFILTER_SAMPLES) + 1.0;
+            color += textureSampleLevel(t_environment, s_environment, light, level).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    return vec4<f32>(color / max(weight, 1e-4), 1.0);
+}
+
+// Scale and bias applied to F0 by the specular BRDF integrated over the
+// hemisphere, with n.v along u and the roughness along v.
+@fragment
+fn fs_brdf(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let n_dot_v = max(in.uv.x, 1e-4);
+    let roughness = in.uv.y;
+    let view = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    // The remapping of k used for image-based lighting.
+    let k = roughness * roughness / 2.0;
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < BRDF_SAMPLES; i++) {
+        let half_vector = importance_sample_ggx(hammersley(i, BRDF_SAMPLES), roughness);
+        let light = reflect(-view, half_vector);
+        let n_dot_l = saturate(light.z);
+        if n_dot_l > 0.0 {
+            let n_dot_h = saturate(half_vector.z);
+            let v_dot_h = saturate(dot(view, half_vector));
+            let geometry = geometry_smith(n_dot_v, n_dot_l, k);
+            let visibility = geometry * v_dot_h / (n_dot_h * n_dot_v);
+            let fresnel = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fresnel) * visibility;
+            bias += fresnel * visibility;
+        }
+    }
+    return vec4<f32>(scale / f32(BRDF_SAMPLES), bias / f32(BRDF_SAMPLES), 0.0, 1.0);
+}
@@ -0,0 +1,26 @@
+// Composites weighted blended order-independent transparency over the scene,
+// after Morgan McGuire and Louis Bavoil's "Weighted Blended Order-Independent
+// Transparency". The transparent surfaces were drawn by fs_oit in shader.wgsl.
+#include "fullscreen.wgsl"
+
+// Sum of the weighted premultiplied colors, and of the weighted alphas.
+@group(0) @binding(0)
+var t_accum: texture_2d<f32>;
+// Product of one minus the alphas, how much of the scene shows through.
+@group(0) @binding(1)
+var t_revealage: texture_2d<f32>;
+
+// The largest value a 16 bit float holds, very close surfaces can overflow the
+// accumulation.
+const MAX_HALF: f32 = 65504.0;
+
+@fragment
+fn fs_composite(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let texel = vec2<i32>(in.clip_position.xy);
+    let accum = textureLoad(t_accum, texel, 0);
+    let revealage = textureLoad(t_revealage, texel, 0).r;
+    // The weighted average color of the surfaces, blended over the scene as
+    // much as all of them together cover it.
+    let color = min(accum.rgb / clamp(accum.a, 1e-4, MAX_HALF), vec3<f32>(MAX_HALF));
+    return vec4<f32>(color, 1.0 - revealage);
+}
@@ -0,0 +1,56 @@
+// The Cook-Torrance microfacet BRDF with the GGX distribution, shared by the
+// scene shader and the image-based lighting precomputation.
+
+const PI: f32 = 3.14159265359;
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a2 = roughness * roughness * roughness * roughness;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+// How much of the microfacets is visible from one side, `k` is remapped from
+// the roughness differently for analytic and image-based lights.
+fn geometry_schlick_ggx(n_dot_x: f32, k: f32) -> f32 {
+    return n_dot_x / (n_dot_x * (1.0 - k) + k);
+}
+
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, k: f32) -> f32 {
+    return geometry_schlick_ggx(n_dot_v, k) * geometry_schlick_ggx(n_dot_l, k);
+}
+
+fn fresnel_schlick(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1.0 - f0) * pow(1.0 - cos_theta, 5.0);
+}
+
+// Fresnel reflectance, reduced for rough surfaces whose microfacets mostly
+// face away from grazing angles.
+fn fresnel_schlick_roughness(n_dot_v: f32, f0: vec3<f32>, roughness: f32) -> vec3<f32> {
+    return f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(1.0 - n_dot_v, 5.0);
+}
+
+// Light of `radiance` arriving from direction `light` reflected towards `view`,
+// all vectors normalized and pointing away from the surface.
+fn cook_torrance(
+    normal: vec3<f32>,
+    view: vec3<f32>,
+    light: vec3<f32>,
+    radiance: vec3<f32>,
+    albedo: vec3<f32>,
+    f0: vec3<f32>,
+    roughness: f32,
+) -> vec3<f32> {
+    let half_vector = normalize(view + light);
+    let n_dot_l = max(dot(normal, light), 0.0);
+    let n_dot_v = max(dot(normal, view), 1e-4);
+    let n_dot_h = max(dot(normal, half_vector), 0.0);
+    let v_dot_h = max(dot(view, half_vector), 0.0);
+
+    // The remapping of k used for analytic lights.
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    let fresnel = fresnel_schlick(v_dot_h, f0);
+    let specular = distribution_ggx(n_dot_h, roughness) * geometry_smith(n_dot_v, n_dot_l, k) * fresnel
+        / (4.0 * n_dot_v * n_dot_l + 1e-4);
+    let diffuse = (1.0 - fresnel) * albedo / PI;
+    return (diffuse + specular) * radiance * n_dot_l;
+}
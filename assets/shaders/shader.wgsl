@@ -1,13 +1,12 @@
 // Vertex shader
-struct CameraUniform {
-    view_proj: mat4x4<f32>,
-};
-@group(1) @binding(0) // 1.
-var<uniform> camera: CameraUniform;
+#include "camera.wgsl"
+#include "pbr.wgsl"
+#include "fog.wgsl"
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
+    @location(2) normal: vec3<f32>,
 }
 
 // struct InstanceInput {
@@ -20,6 +19,10 @@ struct VertexInput {
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
+    @location(1) world_position: vec3<f32>,
+    // From the camera to the vertex, in world space.
+    @location(2) view_offset: vec3<f32>,
+    @location(3) normal: vec3<f32>,
 }
 
 @vertex
@@ -36,17 +39,224 @@ fn vs_main(
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
     out.clip_position = camera.view_proj * vec4<f32>(model.position, 1.0);
+    out.world_position = model.position;
+    out.view_offset = model.position - camera.view_position.xyz;
+    out.normal = model.normal;
     return out;
 }
 // Fragment shader
 
+// Matches FigureLayout in figure.rs, the factors multiply the maps.
 @group(0) @binding(0)
-var t_diffuse: texture_2d<f32>;
+var t_base_color: texture_2d<f32>;
 @group(0)@binding(1)
-var s_diffuse: sampler;
+var s_material: sampler;
+
+// Matches MaterialUniform in model_obj.rs.
+struct MaterialUniform {
+    base_color: vec4<f32>,
+    emissive: vec4<f32>,
+    metallic: f32,
+    roughness: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    alpha_cutoff: f32,
+}
+@group(0) @binding(2)
+var<uniform> material: MaterialUniform;
+// Roughness in green and metalness in blue.
+@group(0) @binding(3)
+var t_metallic_roughness: texture_2d<f32>;
+// Tangent space, with green pointing up the texture like in glTF.
+@group(0) @binding(4)
+var t_normal: texture_2d<f32>;
+@group(0) @binding(5)
+var t_material_occlusion: texture_2d<f32>;
+@group(0) @binding(6)
+var t_emissive: texture_2d<f32>;
+
+// Ambient occlusion of the pixel, 1 where nothing occludes it.
+@group(2) @binding(0)
+var t_occlusion: texture_2d<f32>;
+
+// See fog.wgsl.
+@group(2) @binding(1)
+var<uniform> fog: FogUniform;
+
+// Image-based lighting from the sky, see ibl.wgsl.
+@group(2) @binding(2)
+var t_irradiance: texture_cube<f32>;
+@group(2) @binding(3)
+var t_prefiltered: texture_cube<f32>;
+@group(2) @binding(4)
+var t_brdf_lut: texture_2d<f32>;
+@group(2) @binding(5)
+var s_environment: sampler;
+
+// Matches SunUniform in sun.rs.
+struct SunUniform {
+    // The way the light travels.
+    direction: vec4<f32>,
+    color: vec4<f32>,
+}
+@group(2) @binding(6)
+var<uniform> sun: SunUniform;
+
+// Dielectrics all reflect about this much head on.
+const DIELECTRIC_F0: vec3<f32> = vec3<f32>(0.04);
+// Smoother surfaces have highlights too small for the pixels to catch.
+const MIN_ROUGHNESS: f32 = 0.04;
+
+// The interpolated vertex normal, or the face normal where the model has none.
+fn surface_normal(normal: vec3<f32>, world_position: vec3<f32>, view_offset: vec3<f32>) -> vec3<f32> {
+    var face = cross(dpdy(world_position), dpdx(world_position));
+    // Which way the cross product points depends on the screen's orientation,
+    // the visible side faces the camera.
+    face = normalize(face) * -sign(dot(face, view_offset));
+    let normal_length = length(normal);
+    if normal_length < 1e-4 {
+        return face;
+    }
+    return normal / normal_length;
+}
+
+// Bends `normal` by a tangent space normal from the normal map. The tangent
+// frame comes from the screen space derivatives of the position and texture
+// coordinates, after Christian Schüler's "Normal Mapping Without Precomputed
+// Tangents", so models don't need tangents.
+fn perturb_normal(normal: vec3<f32>, world_position: vec3<f32>, uv: vec2<f32>, mapped: vec3<f32>) -> vec3<f32> {
+    let dp1 = dpdx(world_position);
+    let dp2 = dpdy(world_position);
+    let duv1 = dpdx(uv);
+    let duv2 = dpdy(uv);
+    let dp2_perp = cross(dp2, normal);
+    let dp1_perp = cross(normal, dp1);
+    let tangent = dp2_perp * duv1.x + dp1_perp * duv2.x;
+    let bitangent = dp2_perp * duv1.y + dp1_perp * duv2.y;
+    // Without texture coordinates there is no frame, and nothing to map.
+    let frame_size = max(dot(tangent, tangent), dot(bitangent, bitangent));
+    if frame_size < 1e-20 {
+        return normal;
+    }
+    let scale = inverseSqrt(frame_size);
+    // Texture coordinates grow down the image, the normal map's green up.
+    let frame = mat3x3<f32>(tangent * scale, -bitangent * scale, normal);
+    return normalize(frame * mapped);
+}
+
+// Light the sky reflects off the surface towards the camera, diffuse and
+// specular, with the split-sum approximation.
+fn ambient_light(albedo: vec3<f32>, f0: vec3<f32>, roughness: f32, normal: vec3<f32>, view: vec3<f32>) -> vec3<f32> {
+    let n_dot_v = max(dot(normal, view), 1e-4);
+    let fresnel = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+
+    let irradiance = textureSample(t_irradiance, s_environment, normal).rgb;
+    let diffuse = (1.0 - fresnel) * albedo * irradiance;
+
+    let levels = f32(textureNumLevels(t_prefiltered) - 1u);
+    let reflected = reflect(-view, normal);
+    let prefiltered = textureSampleLevel(t_prefiltered, s_environment, reflected, roughness * levels).rgb;
+    let brdf = textureSample(t_brdf_lut, s_environment, vec2<f32>(n_dot_v, roughness)).rg;
+    let specular = prefiltered * (fresnel * brdf.x + brdf.y);
+
+    return diffuse + specular;
+}
+
+// How much of the pixel the surface covers, from the base color's alpha. The
+// ALPHA_MASK, ALPHA_TO_COVERAGE and ALPHA_BLEND features pick the material's
+// alpha mode, without them surfaces are opaque.
+fn coverage(alpha: f32) -> f32 {
+#ifdef ALPHA_MASK
+#ifdef ALPHA_TO_COVERAGE
+    // Sharpened to an edge about a pixel wide, the hardware turns it into the
+    // samples covered.
+    return saturate((alpha - material.alpha_cutoff) / max(fwidth(alpha), 1e-4) + 0.5);
+#else
+    return step(material.alpha_cutoff, alpha);
+#endif
+#else
+#ifdef ALPHA_BLEND
+    return alpha;
+#else
+    return 1.0;
+#endif
+#endif
+}
+
+// Alpha tested materials leave their cut out parts out of the depth prepass.
+// Only fully covered pixels are written, so that where alpha-to-coverage
+// leaves samples uncovered the geometry behind still gets drawn.
+@fragment
+fn fs_depth_mask(in: VertexOutput) {
+    let alpha = textureSample(t_base_color, s_material, in.tex_coords).a * material.base_color.a;
+    if coverage(alpha) < 1.0 {
+        discard;
+    }
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    return shade(in);
+}
+
+// What oit.rs accumulates blended surfaces in, see oit.wgsl.
+struct OitOutput {
+    @location(0) accum: vec4<f32>,
+    @location(1) revealage: f32,
+}
+
+// Blended surfaces for weighted blended order-independent transparency. Nearer
+// and more opaque surfaces weigh more, with the distance based weight from
+// McGuire and Bavoil's paper.
+@fragment
+fn fs_oit(in: VertexOutput) -> OitOutput {
+    let color = shade(in);
+    let distance = length(in.view_offset);
+    let weight = color.a
+        * clamp(10.0 / (1e-5 + pow(distance / 5.0, 2.0) + pow(distance / 200.0, 6.0)), 1e-2, 3e3);
+    var out: OitOutput;
+    out.accum = vec4<f32>(color.rgb * color.a, color.a) * weight;
+    out.revealage = color.a;
+    return out;
+}
+
+// The lit color of the surface, with the alpha its alpha mode gives it.
+fn shade(in: VertexOutput) -> vec4<f32> {
+    let base_color = textureSample(t_base_color, s_material, in.tex_coords) * material.base_color;
+    let metallic_roughness = textureSample(t_metallic_roughness, s_material, in.tex_coords);
+    let metallic = saturate(metallic_roughness.b * material.metallic);
+    let roughness = clamp(metallic_roughness.g * material.roughness, MIN_ROUGHNESS, 1.0);
+    let material_occlusion = textureSample(t_material_occlusion, s_material, in.tex_coords).r;
+    let emissive = textureSample(t_emissive, s_material, in.tex_coords).rgb * material.emissive.rgb;
+    let mapped = textureSample(t_normal, s_material, in.tex_coords).xyz * 2.0 - 1.0;
+    let alpha = coverage(base_color.a);
+
+    let normal = perturb_normal(
+        surface_normal(in.normal, in.world_position, in.view_offset),
+        in.world_position,
+        in.tex_coords,
+        normalize(mapped * vec3<f32>(material.normal_scale, material.normal_scale, 1.0)),
+    );
+    let view = normalize(-in.view_offset);
+    // Metals have no diffuse reflection and tint the specular one.
+    let albedo = base_color.rgb * (1.0 - metallic);
+    let f0 = mix(DIELECTRIC_F0, base_color.rgb, metallic);
+
+    let direct = cook_torrance(normal, view, -sun.direction.xyz, sun.color.rgb, albedo, f0, roughness);
+    // Both kinds of occlusion only darken the light from the sky. Emitted light
+    // stays. Blended surfaces aren't in the depth SSAO works from, what it found
+    // is behind them.
+    var occlusion = mix(1.0, material_occlusion, material.occlusion_strength);
+#ifndef ALPHA_BLEND
+    occlusion *= textureLoad(t_occlusion, vec2<i32>(in.clip_position.xy), 0).r;
+#endif
+    let ambient = ambient_light(albedo, f0, roughness, normal, view) * occlusion;
+    let color = mix(direct + ambient + emissive, fog.color.rgb, fog_amount(fog, in.world_position, in.view_offset));
+#ifdef ALPHA_MASK
+    if alpha <= 0.0 {
+        discard;
+    }
+#endif
+    return vec4<f32>(color, alpha);
 }
  
\ No newline at end of file
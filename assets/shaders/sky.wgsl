@@ -0,0 +1,40 @@
+// Draws the sky cubemap behind everything else.
+#include "camera.wgsl"
+#include "fog.wgsl"
+
+struct SkyOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // Point on the far plane in homogeneous world coordinates. It is divided
+    // per fragment, the division doesn't interpolate linearly.
+    @location(0) far_position: vec4<f32>,
+    @location(1) view_position: vec3<f32>,
+}
+
+@group(0) @binding(0)
+var t_sky: texture_cube<f32>;
+@group(0) @binding(1)
+var s_sky: sampler;
+@group(0) @binding(2)
+var<uniform> fog: FogUniform;
+
+// A fullscreen triangle on the far plane, so it only shows where the depth
+// buffer is still clear.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> SkyOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    var out: SkyOutput;
+    out.clip_position = vec4<f32>(ndc, 1.0, 1.0);
+    out.far_position = camera.inverse_view_proj * vec4<f32>(ndc, 1.0, 1.0);
+    out.view_position = camera.view_position.xyz;
+    return out;
+}
+
+@fragment
+fn fs_main(in: SkyOutput) -> @location(0) vec4<f32> {
+    let direction = in.far_position.xyz / in.far_position.w - in.view_position;
+    let sky = textureSample(t_sky, s_sky, direction).rgb;
+    // The sky is further away than anything, so it is at least as foggy.
+    let amount = sky_fog_amount(fog, in.view_position.y, direction);
+    return vec4<f32>(mix(sky, fog.color.rgb, amount), 1.0);
+}
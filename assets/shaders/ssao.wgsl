@@ -0,0 +1,143 @@
+// Screen-space ambient occlusion: points in a hemisphere over each pixel's
+// surface are tested against the depth buffer, and the share of them that ends
+// up inside geometry is how much ambient light the pixel loses. The result is
+// noisy by design and blurred afterwards without crossing depth edges.
+#include "fullscreen.wgsl"
+
+const KERNEL_SIZE: u32 = 16u;
+// Matches NOISE_SIZE in ssao.rs.
+const NOISE_SIZE: i32 = 4;
+const BLUR_RADIUS: i32 = 4;
+
+struct SsaoUniform {
+    projection: mat4x4<f32>,
+    inverse_projection: mat4x4<f32>,
+    // Offsets in the hemisphere around +z, scaled to at most 1.
+    kernel: array<vec4<f32>, KERNEL_SIZE>,
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+}
+
+#ifdef MULTISAMPLED
+@group(0) @binding(0)
+var t_depth: texture_depth_multisampled_2d;
+#else
+@group(0) @binding(0)
+var t_depth: texture_depth_2d;
+#endif
+
+@group(1) @binding(0)
+var<uniform> ssao: SsaoUniform;
+
+// The kernel rotations in the occlusion pass, the occlusion in the blur passes.
+@group(2) @binding(0)
+var t_input: texture_2d<f32>;
+
+fn depth_at(coords: vec2<i32>) -> f32 {
+    let size = vec2<i32>(textureDimensions(t_depth));
+    // With MSAA the first sample stands in for the pixel.
+    return textureLoad(t_depth, clamp(coords, vec2<i32>(0), size - 1), 0);
+}
+
+// View space position of what was drawn at pixel `coords`.
+fn view_position(coords: vec2<i32>) -> vec3<f32> {
+    let uv = (vec2<f32>(coords) + 0.5) / vec2<f32>(textureDimensions(t_depth));
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth_at(coords), 1.0);
+    let position = ssao.inverse_projection * ndc;
+    return position.xyz / position.w;
+}
+
+// Reconstructs the normal from the neighbouring pixels. On each axis the
+// neighbour closer in depth is used, so normals don't bend over edges.
+fn view_normal(coords: vec2<i32>, center: vec3<f32>) -> vec3<f32> {
+    let left = view_position(coords - vec2<i32>(1, 0));
+    let right = view_position(coords + vec2<i32>(1, 0));
+    let up = view_position(coords - vec2<i32>(0, 1));
+    let down = view_position(coords + vec2<i32>(0, 1));
+
+    var dx = right - center;
+    if abs(center.z - left.z) < abs(dx.z) {
+        dx = center - left;
+    }
+    var dy = down - center;
+    if abs(center.z - up.z) < abs(dy.z) {
+        dy = center - up;
+    }
+    // Pixel rows go down the screen, so this faces the camera.
+    return normalize(cross(dy, dx));
+}
+
+@fragment
+fn fs_occlusion(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let coords = vec2<i32>(in.clip_position.xy);
+    // Nothing was drawn here.
+    if depth_at(coords) >= 1.0 {
+        return vec4<f32>(1.0);
+    }
+    let position = view_position(coords);
+    let normal = view_normal(coords, position);
+
+    // Every pixel of a noise tile rotates the kernel differently, which trades
+    // banding for noise the blur takes out.
+    let rotation = textureLoad(t_input, coords % vec2<i32>(NOISE_SIZE), 0).xyz;
+    var tangent = rotation - normal * dot(rotation, normal);
+    if dot(tangent, tangent) < 1e-4 {
+        tangent = cross(normal, vec3<f32>(0.0, 0.0, 1.0));
+    }
+    tangent = normalize(tangent);
+    let tbn = mat3x3<f32>(tangent, cross(normal, tangent), normal);
+
+    let size = vec2<f32>(textureDimensions(t_depth));
+    var occlusion = 0.0;
+    for (var i = 0u; i < KERNEL_SIZE; i++) {
+        let sample_position = position + tbn * ssao.kernel[i].xyz * ssao.radius;
+        let clip = ssao.projection * vec4<f32>(sample_position, 1.0);
+        let ndc = clip.xy / clip.w;
+        let sample_coords = vec2<i32>(vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5) * size);
+        let surface = view_position(sample_coords).z;
+
+        // Geometry far in front of the point is in the way of the sample, but
+        // too far away to occlude the point itself.
+        let range = smoothstep(0.0, 1.0, ssao.radius / abs(position.z - surface));
+        if surface >= sample_position.z + ssao.bias {
+            occlusion += range;
+        }
+    }
+    let visibility = 1.0 - occlusion / f32(KERNEL_SIZE);
+    return vec4<f32>(pow(visibility, ssao.intensity));
+}
+
+// Gaussian blur whose samples count less the further their depth is from the
+// center's, so occlusion doesn't bleed onto surfaces in front or behind.
+fn blur(coords: vec2<i32>, direction: vec2<i32>) -> f32 {
+    if depth_at(coords) >= 1.0 {
+        return 1.0;
+    }
+    let center = view_position(coords).z;
+    let size = vec2<i32>(textureDimensions(t_input));
+    let sigma = f32(BLUR_RADIUS) * 0.5;
+
+    var total = 0.0;
+    var weights = 0.0;
+    for (var i = -BLUR_RADIUS; i <= BLUR_RADIUS; i++) {
+        let sample_coords = clamp(coords + direction * i, vec2<i32>(0), size - 1);
+        let depth = view_position(sample_coords).z;
+        let spatial = exp(-f32(i * i) / (2.0 * sigma * sigma));
+        let range = exp(-abs(depth - center) / (ssao.radius * 0.25));
+        let weight = spatial * range;
+        total += textureLoad(t_input, sample_coords, 0).r * weight;
+        weights += weight;
+    }
+    return total / weights;
+}
+
+@fragment
+fn fs_blur_horizontal(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(blur(vec2<i32>(in.clip_position.xy), vec2<i32>(1, 0)));
+}
+
+@fragment
+fn fs_blur_vertical(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(blur(vec2<i32>(in.clip_position.xy), vec2<i32>(0, 1)));
+}
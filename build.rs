@@ -1,18 +1,62 @@
-use anyhow::*;
-use fs_extra::copy_items;
-use fs_extra::dir::CopyOptions;
-use std::env;
-
-fn main() -> Result<()> {
-    // This tells Cargo to rerun this script if something in /res/ changes.
-    println!("cargo:rerun-if-changed=assets/*");
-
-    let out_dir = env::var("OUT_DIR")?;
-    let mut copy_options = CopyOptions::new();
-    copy_options.overwrite = true;
-    let mut paths_to_copy = Vec::new();
-    paths_to_copy.push("assets");
-    copy_items(&paths_to_copy, out_dir, &copy_options)?;
+//! Validates every shader in `assets/shaders` with naga, so WGSL errors fail the
+//! build instead of showing up when a pipeline is created.
 
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+
+// Some of it is only used by the renderer.
+#[allow(dead_code)]
+#[path = "src/render/preprocessor.rs"]
+mod preprocessor;
+
+const SHADER_DIR: &str = "assets/shaders";
+
+fn main() -> anyhow::Result<()> {
+    println!("cargo:rerun-if-changed={}", SHADER_DIR);
+    println!("cargo:rerun-if-changed=src/render/preprocessor.rs");
+
+    let mut read = |file_name: &str| -> anyhow::Result<String> {
+        let path = Path::new("assets").join(file_name);
+        std::fs::read_to_string(&path).with_context(|| format!("Could not read {:?}", path))
+    };
+
+    let mut shaders = std::fs::read_dir(SHADER_DIR)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    shaders.retain(|path| path.extension().is_some_and(|ext| ext == "wgsl"));
+    shaders.sort();
+
+    for path in shaders {
+        let file_name = format!("shaders/{}", path.file_name().unwrap().to_string_lossy());
+
+        // Every feature the shader checks for makes another variant. Pipelines
+        // combine them, e.g. ALPHA_MASK with ALPHA_TO_COVERAGE, so validate every
+        // combination. Shaders only check a handful, so there aren't many.
+        let plain = preprocessor::preprocess(&file_name, &[], &mut read)?;
+        let conditions = plain.conditions.iter().map(String::as_str).collect::<Vec<_>>();
+        validate(&file_name, &[], &plain)?;
+        for combination in 1..1u32 << conditions.len() {
+            let features = conditions
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| combination & (1 << i) != 0)
+                .map(|(_, feature)| *feature)
+                .collect::<Vec<_>>();
+            let variant = preprocessor::preprocess(&file_name, &features, &mut read)?;
+            validate(&file_name, &features, &variant)?;
+        }
+    }
+    Ok(())
+}
+
+fn validate(file_name: &str, features: &[&str], shader: &preprocessor::Preprocessed) -> anyhow::Result<()> {
+    let error = |message: String| anyhow!("{} with features {:?}:\n{}", file_name, features, shader.map_locations(file_name, &message));
+
+    let module = naga::front::wgsl::parse_str(&shader.source)
+        .map_err(|e| error(e.emit_to_string_with_path(&shader.source, file_name)))?;
+    naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::empty())
+        .validate(&module)
+        .map_err(|e| error(e.emit_to_string_with_path(&shader.source, file_name)))?;
     Ok(())
-}
\ No newline at end of file
+}
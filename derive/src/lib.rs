@@ -0,0 +1,196 @@
+//! `#[derive(Vertex)]` for the renderer's vertex types.
+//!
+//! The generated impl refers to `crate::render::Vertex` and `wgpu`, so it only
+//! works inside the `rust-graphics` crate.
+
+use proc_macro2::{Span, TokenStream};
+use quote::{quote, ToTokens};
+use syn::{parse_macro_input, spanned::Spanned, Data, DeriveInput, Error, Fields, Ident, LitInt, Member, Type};
+
+/// Every `wgpu::VertexFormat` a field can be given with `#[vertex(format = ...)]`.
+const FORMATS: &[&str] = &[
+    "Uint8x2", "Uint8x4", "Sint8x2", "Sint8x4", "Unorm8x2", "Unorm8x4", "Snorm8x2", "Snorm8x4", "Uint16x2",
+    "Uint16x4", "Sint16x2", "Sint16x4", "Unorm16x2", "Unorm16x4", "Snorm16x2", "Snorm16x4", "Float16x2",
+    "Float16x4", "Float32", "Float32x2", "Float32x3", "Float32x4", "Uint32", "Uint32x2", "Uint32x3", "Uint32x4",
+    "Sint32", "Sint32x2", "Sint32x3", "Sint32x4", "Float64", "Float64x2", "Float64x3", "Float64x4",
+];
+
+/// Implements `render::Vertex`, with one attribute per field at consecutive shader
+/// locations. Formats follow from the field types: `f32`, `u32`, `i32` and `f64`,
+/// arrays of them, and `[[T; N]; M]` matrices, which take `M` locations. Other
+/// types need an explicit format.
+///
+/// ```ignore
+/// #[repr(C)]
+/// #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Vertex)]
+/// #[vertex(location = 5, step_mode = Instance)]
+/// pub struct Instance {
+///     pub model: [[f32; 4]; 4],
+///     #[vertex(format = Unorm8x4)]
+///     pub color: [u8; 4],
+/// }
+/// ```
+///
+/// On the struct, `location` is the first shader location (0 by default),
+/// `step_mode` is `Vertex` or `Instance`, and `quads_index` the index format
+/// used to draw quads, if any.
+#[proc_macro_derive(Vertex, attributes(vertex))]
+pub fn derive_vertex(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input).unwrap_or_else(Error::into_compile_error).into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream> {
+    if !input.generics.params.is_empty() {
+        return Err(Error::new(input.generics.span(), "Vertex can't be derived for generic types"));
+    }
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => return Err(Error::new(input.ident.span(), "Vertex can only be derived for structs")),
+    };
+
+    let mut location = 0u32;
+    let mut step_mode = Ident::new("Vertex", Span::call_site());
+    let mut quads_index = None;
+    for attr in input.attrs.iter().filter(|attr| attr.path().is_ident("vertex")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("location") {
+                location = meta.value()?.parse::<LitInt>()?.base10_parse()?;
+            } else if meta.path.is_ident("step_mode") {
+                let mode: Ident = meta.value()?.parse()?;
+                if mode != "Vertex" && mode != "Instance" {
+                    return Err(Error::new(mode.span(), "expected `Vertex` or `Instance`"));
+                }
+                step_mode = mode;
+            } else if meta.path.is_ident("quads_index") {
+                let format: Ident = meta.value()?.parse()?;
+                if format != "Uint16" && format != "Uint32" {
+                    return Err(Error::new(format.span(), "expected `Uint16` or `Uint32`"));
+                }
+                quads_index = Some(format);
+            } else {
+                return Err(meta.error("expected `location`, `step_mode` or `quads_index`"));
+            }
+            Ok(())
+        })?;
+    }
+
+    let members: Vec<Member> = match fields {
+        Fields::Named(fields) => fields.named.iter().map(|f| Member::Named(f.ident.clone().unwrap())).collect(),
+        _ => (0..fields.len()).map(|i| Member::Unnamed(i.into())).collect(),
+    };
+
+    let mut attributes = Vec::new();
+    for (field, member) in fields.iter().zip(members) {
+        let mut format = None;
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("vertex")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("format") {
+                    let name: Ident = meta.value()?.parse()?;
+                    if !FORMATS.iter().any(|format| name == format) {
+                        return Err(Error::new(name.span(), "not a wgpu::VertexFormat"));
+                    }
+                    format = Some(name);
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `format`"))
+                }
+            })?;
+        }
+
+        // A matrix is read as one attribute per row.
+        let (format, rows, row_type) = match format {
+            Some(format) => (format, 1, field.ty.clone()),
+            None => infer_format(&field.ty)?,
+        };
+        for row in 0..rows {
+            let shader_location = location;
+            location += 1;
+            attributes.push(quote! {
+                wgpu::VertexAttribute {
+                    format: wgpu::VertexFormat::#format,
+                    offset: (::std::mem::offset_of!(Self, #member) + #row * ::std::mem::size_of::<#row_type>())
+                        as wgpu::BufferAddress,
+                    shader_location: #shader_location,
+                }
+            });
+        }
+    }
+
+    let name = &input.ident;
+    let quads_index = quads_index.map(|format| {
+        quote! {
+            const QUADS_INDEX: Option<wgpu::IndexFormat> = Some(wgpu::IndexFormat::#format);
+        }
+    });
+    Ok(quote! {
+        impl crate::render::Vertex for #name {
+            const STEP_MODE: wgpu::VertexStepMode = wgpu::VertexStepMode::#step_mode;
+            const ATTRIBUTES: &'static [wgpu::VertexAttribute] = &[#(#attributes),*];
+            #quads_index
+        }
+    })
+}
+
+/// The format of a field, and for matrices how many rows of which type it has.
+fn infer_format(ty: &Type) -> syn::Result<(Ident, usize, Type)> {
+    let unsupported = || {
+        Error::new(
+            ty.span(),
+            format!(
+                "can't infer a vertex format for `{}`, add #[vertex(format = ...)]",
+                ty.to_token_stream()
+            ),
+        )
+    };
+
+    if let Type::Array(array) = ty {
+        let len = array_len(&array.len).ok_or_else(unsupported)?;
+        if let Type::Array(_) = &*array.elem {
+            // Matrix, the rows must be vectors themselves.
+            let (format, 1, _) = infer_format(&array.elem)? else {
+                return Err(unsupported());
+            };
+            return Ok((format, len, (*array.elem).clone()));
+        }
+        let scalar = scalar_format(&array.elem).ok_or_else(unsupported)?;
+        let name = match len {
+            1 => scalar.to_string(),
+            _ => format!("{}x{}", scalar, len),
+        };
+        if !FORMATS.contains(&name.as_str()) {
+            return Err(unsupported());
+        }
+        return Ok((Ident::new(&name, ty.span()), 1, ty.clone()));
+    }
+
+    match scalar_format(ty) {
+        Some(scalar) if FORMATS.contains(&scalar) => Ok((Ident::new(scalar, ty.span()), 1, ty.clone())),
+        _ => Err(unsupported()),
+    }
+}
+
+fn scalar_format(ty: &Type) -> Option<&'static str> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let ident = path.path.get_ident()?;
+    Some(match ident.to_string().as_str() {
+        "f32" => "Float32",
+        "f64" => "Float64",
+        "u32" => "Uint32",
+        "i32" => "Sint32",
+        "u16" => "Uint16",
+        "i16" => "Sint16",
+        "u8" => "Uint8",
+        "i8" => "Sint8",
+        _ => return None,
+    })
+}
+
+fn array_len(len: &syn::Expr) -> Option<usize> {
+    match len {
+        syn::Expr::Lit(syn::ExprLit { lit: syn::Lit::Int(int), .. }) => int.base10_parse().ok(),
+        _ => None,
+    }
+}
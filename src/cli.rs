@@ -0,0 +1,372 @@
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+use instant::Duration;
+
+use crate::launcher::LaunchOptions;
+use crate::render::settings::{FogMode, PostAntiAliasing, PresentMode, Tonemapper, Transparency};
+use crate::scene::Sky;
+
+/// Quick wgpu model viewer and batch renderer.
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// Model to show, either a path or a file name inside the assets folder
+    #[arg(default_value = "cube.obj")]
+    model: String,
+
+    /// Folder to look for assets in, searched before RUST_GRAPHICS_ASSETS and the
+    /// defaults. Can be given more than once
+    #[arg(long = "assets", value_name = "DIR")]
+    assets: Vec<PathBuf>,
+
+    /// Window size in physical pixels, e.g. 1280x720
+    #[arg(long, value_parser = parse_size)]
+    size: Option<(u32, u32)>,
+
+    /// Graphics API to render with
+    #[arg(long, value_enum, default_value_t = Backend::Auto)]
+    backend: Backend,
+
+    /// How frames are presented: vsync, mailbox or immediate
+    #[arg(long, default_value = "vsync", env = "RUST_GRAPHICS_PRESENT_MODE")]
+    present_mode: PresentMode,
+
+    /// Samples per pixel for multisample anti-aliasing
+    #[arg(long, default_value_t = 1, value_parser = parse_msaa)]
+    msaa: u32,
+
+    /// Anti-aliasing of the final image, which also smooths edges inside
+    /// textures: off or fxaa
+    #[arg(long, default_value = "off")]
+    post_aa: PostAntiAliasing,
+
+    /// Tonemapping curve: aces, reinhard or agx
+    #[arg(long, default_value = "aces")]
+    tonemapper: Tonemapper,
+
+    /// Exposure the scene is multiplied by before tonemapping
+    #[arg(long, default_value_t = 1.0, value_parser = parse_positive)]
+    exposure: f32,
+
+    /// Add a glow around bright and emissive parts of the scene
+    #[arg(long)]
+    bloom: bool,
+
+    /// Brightness above which the scene glows
+    #[arg(long, default_value_t = 1.0, value_parser = parse_non_negative)]
+    bloom_threshold: f32,
+
+    /// Strength of the glow
+    #[arg(long, default_value_t = 0.5, value_parser = parse_non_negative)]
+    bloom_intensity: f32,
+
+    /// Spread of the glow
+    #[arg(long, default_value_t = 1.0, value_parser = parse_non_negative)]
+    bloom_radius: f32,
+
+    /// Enable ambient occlusion, which darkens creases and contact points
+    #[arg(long)]
+    ssao: bool,
+
+    /// Distance in world units within which geometry occludes ambient light
+    #[arg(long, default_value_t = 0.5, value_parser = parse_positive)]
+    ssao_radius: f32,
+
+    /// Strength of the ambient occlusion
+    #[arg(long, default_value_t = 1.0, value_parser = parse_non_negative)]
+    ssao_intensity: f32,
+
+    /// Fog distant geometry fades into: off, linear, exponential or height
+    #[arg(long, default_value = "off")]
+    fog: FogMode,
+
+    /// Linear color of the fog, e.g. 0.5,0.5,1
+    #[arg(long, value_parser = parse_color)]
+    fog_color: Option<[f32; 3]>,
+
+    /// Extinction per world unit of exponential and height fog
+    #[arg(long, default_value_t = 0.02, value_parser = parse_non_negative)]
+    fog_density: f32,
+
+    /// Distance linear fog starts at
+    #[arg(long, default_value_t = 40.0, value_parser = parse_non_negative)]
+    fog_start: f32,
+
+    /// Distance past which linear fog hides everything
+    #[arg(long, default_value_t = 100.0, value_parser = parse_non_negative)]
+    fog_end: f32,
+
+    /// Altitude height fog settles at
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    fog_height: f32,
+
+    /// How blended materials are layered: sorted back to front, or oit for
+    /// weighted blended order-independent transparency
+    #[arg(long, default_value = "sorted")]
+    transparency: Transparency,
+
+    /// What is shown behind the scene: a linear color like 0.5,0.5,1, six
+    /// comma separated cubemap faces in the order +X,-X,+Y,-Y,+Z,-Z, or an
+    /// equirectangular panorama such as an .hdr file
+    #[arg(long, value_parser = parse_sky)]
+    sky: Option<Sky>,
+
+    /// Direction the sunlight travels in, e.g. -0.4,-1,-0.6
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    sun_direction: Option<[f32; 3]>,
+
+    /// Linear color of the sunlight times its intensity, 0,0,0 turns it off
+    #[arg(long, value_parser = parse_color)]
+    sun_color: Option<[f32; 3]>,
+
+    /// Camera start position, e.g. 0,5,10
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    camera_position: Option<[f32; 3]>,
+
+    /// Camera start yaw in degrees
+    #[arg(long, allow_negative_numbers = true)]
+    camera_yaw: Option<f32>,
+
+    /// Camera start pitch in degrees
+    #[arg(long, allow_negative_numbers = true)]
+    camera_pitch: Option<f32>,
+
+    /// Render offscreen and save the last frame to this PNG file. Runs without
+    /// a window, so no display is needed
+    #[arg(long)]
+    screenshot: Option<PathBuf>,
+
+    /// Render offscreen and exit after this many frames. Runs without a window,
+    /// so no display is needed
+    #[arg(long)]
+    frames: Option<u32>,
+
+    /// Render offscreen without a window, so no display is needed
+    #[arg(long, env = "RUST_GRAPHICS_HEADLESS", value_parser = parse_presence, default_value = "")]
+    headless: bool,
+
+    /// Record input events to this file
+    #[arg(long, env = "RUST_GRAPHICS_RECORD")]
+    record: Option<PathBuf>,
+
+    /// Replay input events from this file instead of reading live input
+    #[arg(long, env = "RUST_GRAPHICS_REPLAY")]
+    replay: Option<PathBuf>,
+
+    /// Fixed time step in seconds used while replaying [default: 1/60]
+    #[arg(
+        long,
+        default_value_t = 1.0 / 60.0,
+        hide_default_value = true,
+        value_parser = parse_seconds,
+        env = "RUST_GRAPHICS_REPLAY_DT"
+    )]
+    replay_dt: f64,
+
+    /// Maximum frames per second
+    #[arg(long, env = "RUST_GRAPHICS_FPS_CAP")]
+    fps_cap: Option<u32>,
+
+    /// Only redraw on input or while something is moving
+    #[arg(long, env = "RUST_GRAPHICS_LOW_POWER", value_parser = parse_presence, default_value = "")]
+    low_power: bool,
+
+    /// Reload shaders, textures and models when their files change, true or false
+    /// [default: true in debug builds]
+    #[arg(long, env = "RUST_GRAPHICS_HOT_RELOAD")]
+    hot_reload: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Backend {
+    Auto,
+    Vulkan,
+    Gl,
+    Metal,
+    Dx12,
+}
+
+impl Backend {
+    fn to_wgpu(self) -> wgpu::Backends {
+        match self {
+            Backend::Auto => wgpu::Backends::all(),
+            Backend::Vulkan => wgpu::Backends::VULKAN,
+            Backend::Gl => wgpu::Backends::GL,
+            Backend::Metal => wgpu::Backends::METAL,
+            Backend::Dx12 => wgpu::Backends::DX12,
+        }
+    }
+}
+
+impl Cli {
+    pub fn into_options(self) -> LaunchOptions {
+        let mut options = LaunchOptions {
+            assets: self.assets,
+            size: self.size,
+            record: self.record,
+            replay: self.replay,
+            fps_cap: self.fps_cap,
+            low_power: self.low_power,
+            frames: self.frames,
+            ..Default::default()
+        };
+
+        options.scene.model = resolve_path(self.model);
+        if let Some(sky) = self.sky {
+            options.scene.sky = match sky {
+                Sky::Color(color) => Sky::Color(color),
+                Sky::Cubemap(faces) => Sky::Cubemap(faces.map(resolve_path)),
+                Sky::Equirectangular(file) => Sky::Equirectangular(resolve_path(file)),
+            };
+        }
+        if let Some(direction) = self.sun_direction {
+            options.scene.sun.direction = direction;
+        }
+        if let Some(color) = self.sun_color {
+            options.scene.sun.color = color;
+        }
+        if let Some([x, y, z]) = self.camera_position {
+            options.scene.camera_position = cgmath::Point3::new(x, y, z);
+        }
+        if let Some(yaw) = self.camera_yaw {
+            options.scene.camera_yaw = cgmath::Deg(yaw);
+        }
+        if let Some(pitch) = self.camera_pitch {
+            options.scene.camera_pitch = cgmath::Deg(pitch);
+        }
+
+        options.render.backends = self.backend.to_wgpu();
+        options.render.present_mode = self.present_mode;
+        options.render.sample_count = self.msaa;
+        options.render.post_anti_aliasing = self.post_aa;
+        options.render.tonemapper = self.tonemapper;
+        options.render.exposure = self.exposure;
+        options.render.bloom.enabled = self.bloom;
+        options.render.bloom.threshold = self.bloom_threshold;
+        options.render.bloom.intensity = self.bloom_intensity;
+        options.render.bloom.radius = self.bloom_radius;
+        options.render.ssao.enabled = self.ssao;
+        options.render.ssao.radius = self.ssao_radius;
+        options.render.ssao.intensity = self.ssao_intensity;
+        options.render.fog.mode = self.fog;
+        // Fog blends best into a plain sky of the same color.
+        match (self.fog_color, &options.scene.sky) {
+            (Some(color), _) | (None, &Sky::Color(color)) => options.render.fog.color = color,
+            _ => {}
+        }
+        options.render.fog.density = self.fog_density;
+        options.render.fog.start = self.fog_start;
+        options.render.fog.end = self.fog_end;
+        options.render.fog.height = self.fog_height;
+        options.render.transparency = self.transparency;
+        if let Some(hot_reload) = self.hot_reload {
+            options.render.hot_reload = hot_reload;
+        }
+        // Batch runs go without a window, so they work on machines without a
+        // display. Swapchain images can't be read back either, screenshots need
+        // an offscreen target.
+        options.render.headless = self.headless || self.screenshot.is_some() || self.frames.is_some();
+
+        options.replay_dt = Duration::from_secs_f64(self.replay_dt);
+
+        // Without a replay to finish, a screenshot is taken of the first frame.
+        if self.screenshot.is_some() && options.replay.is_none() && options.frames.is_none() {
+            options.frames = Some(1);
+        }
+        options.screenshot = self.screenshot;
+
+        options
+    }
+}
+
+/// Paths that exist relative to the working directory are made absolute, so
+/// they don't get looked up inside the assets folder.
+fn resolve_path(file: String) -> String {
+    match std::fs::canonicalize(Path::new(&file)) {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => file,
+    }
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = s
+        .split_once('x')
+        .ok_or_else(|| format!("expected WIDTHxHEIGHT, got {:?}", s))?;
+    let parse = |v: &str| match v.trim().parse::<u32>() {
+        Ok(v) if v > 0 => Ok(v),
+        _ => Err(format!("invalid size {:?}", s)),
+    };
+    Ok((parse(width)?, parse(height)?))
+}
+
+fn parse_msaa(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(samples @ (1 | 2 | 4 | 8)) => Ok(samples),
+        _ => Err(format!("MSAA must be 1, 2, 4 or 8, got {:?}", s)),
+    }
+}
+
+fn parse_non_negative(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(value) if value >= 0.0 && value.is_finite() => Ok(value),
+        _ => Err(format!("expected a number of at least 0, got {:?}", s)),
+    }
+}
+
+fn parse_positive(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(value) if value > 0.0 && value.is_finite() => Ok(value),
+        _ => Err(format!("expected a positive number, got {:?}", s)),
+    }
+}
+
+/// Switches set from the environment are on for any non-empty value, as they
+/// were before they became flags. They default to an empty value, clap's
+/// usual "false" would count as set.
+fn parse_presence(s: &str) -> Result<bool, String> {
+    Ok(!s.is_empty())
+}
+
+fn parse_color(s: &str) -> Result<[f32; 3], String> {
+    let color = parse_vec3(s)?;
+    if color.iter().any(|c| *c < 0.0 || !c.is_finite()) {
+        return Err(format!("color channels can't be negative, got {:?}", s));
+    }
+    Ok(color)
+}
+
+fn parse_sky(s: &str) -> Result<Sky, String> {
+    let parts: Vec<&str> = s.split(',').map(str::trim).collect();
+    if parts.iter().any(|part| part.is_empty()) {
+        return Err(format!("empty entry in {:?}", s));
+    }
+    match parts.len() {
+        1 => Ok(Sky::Equirectangular(s.trim().to_string())),
+        3 => parse_color(s).map(Sky::Color),
+        6 => Ok(Sky::Cubemap(std::array::from_fn(|i| parts[i].to_string()))),
+        _ => Err(format!(
+            "expected a color, six cubemap faces or a single panorama, got {:?}",
+            s
+        )),
+    }
+}
+
+fn parse_seconds(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Ok(seconds),
+        _ => Err(format!("expected a positive number of seconds, got {:?}", s)),
+    }
+}
+
+fn parse_vec3(s: &str) -> Result<[f32; 3], String> {
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("invalid number in {:?}: {}", s, e))?;
+    values
+        .try_into()
+        .map_err(|_| format!("expected three comma separated values, got {:?}", s))
+}
+
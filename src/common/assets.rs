@@ -0,0 +1,844 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{BufReader, Cursor};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+
+use crate::render::model_obj::{Material, MaterialFactors, MaterialTextures, Mesh, Model};
+use crate::render::texture::{ColorSpace, Texture};
+
+use super::loader::{Job, Loaded, Loader};
+use super::resources::{self, MaterialData, MeshData, ModelData, TextureRef};
+
+/// How many bytes of textures and meshes are uploaded to the GPU per frame.
+pub const DEFAULT_UPLOAD_BUDGET: usize = 8 * 1024 * 1024;
+
+/// A reference to an asset owned by an [`AssetServer`].
+///
+/// Handles are cheap to clone. Once the last handle to an asset is dropped the
+/// server unloads it on the next [`AssetServer::collect_unused`].
+pub struct Handle<T> {
+    id: u64,
+    alive: Arc<()>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id,
+            alive: self.alive.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> std::hash::Hash for Handle<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle<{}>({})", std::any::type_name::<T>(), self.id)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadState {
+    Loading,
+    Loaded,
+    Failed(String),
+}
+
+enum Slot<T> {
+    Loading,
+    Loaded(T),
+    Failed(String),
+}
+
+struct Entry<T> {
+    key: PathBuf,
+    slot: Slot<T>,
+    handles: Weak<()>,
+}
+
+/// Storage for all assets of one type, deduplicated by path.
+pub struct Assets<T> {
+    entries: HashMap<u64, Entry<T>>,
+    by_key: HashMap<PathBuf, u64>,
+}
+
+impl<T> Default for Assets<T> {
+    fn default() -> Self {
+        Self {
+            entries: HashMap::new(),
+            by_key: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Assets<T> {
+    /// Returns a handle to the asset stored under `key`, if there is one, and
+    /// whether it has to be loaded again. Assets whose handles were all dropped
+    /// but that weren't collected yet are revived, ones that failed to load go
+    /// back to loading so the caller tries again.
+    fn find(&mut self, key: &PathBuf) -> Option<(Handle<T>, bool)> {
+        let id = *self.by_key.get(key)?;
+        let entry = self.entries.get_mut(&id)?;
+        let alive = entry.handles.upgrade().unwrap_or_else(|| {
+            let alive = Arc::new(());
+            entry.handles = Arc::downgrade(&alive);
+            alive
+        });
+        let failed = matches!(entry.slot, Slot::Failed(_));
+        if failed {
+            entry.slot = Slot::Loading;
+        }
+        Some((Handle { id, alive, marker: PhantomData }, failed))
+    }
+
+    fn reserve(&mut self, id: u64, key: PathBuf) -> Handle<T> {
+        let alive = Arc::new(());
+        self.by_key.insert(key.clone(), id);
+        self.entries.insert(id, Entry {
+            key,
+            slot: Slot::Loading,
+            handles: Arc::downgrade(&alive),
+        });
+        Handle { id, alive, marker: PhantomData }
+    }
+
+    /// Whether the asset is still around, it may have been unloaded while loading.
+    fn contains(&self, id: u64) -> bool {
+        self.entries.contains_key(&id)
+    }
+
+    fn label(&self, id: u64) -> String {
+        self.entries
+            .get(&id)
+            .map(|entry| entry.key.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    fn finish(&mut self, id: u64, result: anyhow::Result<T>) {
+        if let Some(entry) = self.entries.get_mut(&id) {
+            match (result, &entry.slot) {
+                (Ok(asset), _) => entry.slot = Slot::Loaded(asset),
+                // A reload that fails shouldn't lose what was working before.
+                (Err(e), Slot::Loaded(_)) => {
+                    log::error!("Could not reload {:?}, keeping the previous version: {:?}", entry.key, e)
+                }
+                (Err(e), _) => {
+                    log::error!("Could not load {:?}: {:?}", entry.key, e);
+                    entry.slot = Slot::Failed(format!("{:#}", e));
+                }
+            }
+        }
+    }
+
+    fn is_loaded(&self, id: u64) -> bool {
+        matches!(self.entries.get(&id).map(|entry| &entry.slot), Some(Slot::Loaded(_)))
+    }
+
+    fn id_of(&self, key: &Path) -> Option<u64> {
+        self.by_key.get(key).copied()
+    }
+
+    pub fn get(&self, handle: &Handle<T>) -> Option<&T> {
+        match &self.entries.get(&handle.id)?.slot {
+            Slot::Loaded(asset) => Some(asset),
+            _ => None,
+        }
+    }
+
+    pub fn load_state(&self, handle: &Handle<T>) -> LoadState {
+        match self.entries.get(&handle.id).map(|entry| &entry.slot) {
+            Some(Slot::Loaded(_)) => LoadState::Loaded,
+            Some(Slot::Failed(e)) => LoadState::Failed(e.clone()),
+            // Can't happen while the handle is alive, but the closest answer is that
+            // the asset still has to be loaded.
+            Some(Slot::Loading) | None => LoadState::Loading,
+        }
+    }
+
+    /// Drops every asset nobody holds a handle to, returns how many were dropped.
+    fn collect_unused(&mut self) -> usize {
+        let unused = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.handles.strong_count() == 0)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in &unused {
+            if let Some(entry) = self.entries.remove(id) {
+                log::debug!("Unloading {:?}", entry.key);
+                self.by_key.remove(&entry.key);
+            }
+        }
+        unused.len()
+    }
+}
+
+/// Types an [`AssetServer`] can store.
+pub trait Asset: Sized {
+    fn assets(server: &AssetServer) -> &Assets<Self>;
+    /// Shown in place of assets that are still loading or failed to load.
+    fn placeholder(server: &AssetServer) -> &Handle<Self>;
+}
+
+impl Asset for Texture {
+    fn assets(server: &AssetServer) -> &Assets<Self> {
+        &server.textures
+    }
+
+    fn placeholder(server: &AssetServer) -> &Handle<Self> {
+        &server.placeholders.texture
+    }
+}
+
+impl Asset for Material {
+    fn assets(server: &AssetServer) -> &Assets<Self> {
+        &server.materials
+    }
+
+    fn placeholder(server: &AssetServer) -> &Handle<Self> {
+        &server.placeholders.material
+    }
+}
+
+impl Asset for Model {
+    fn assets(server: &AssetServer) -> &Assets<Self> {
+        &server.models
+    }
+
+    fn placeholder(server: &AssetServer) -> &Handle<Self> {
+        &server.placeholders.model
+    }
+}
+
+/// Decoded data waiting to be uploaded to the GPU.
+enum Upload {
+    Texture {
+        id: u64,
+        image: image::RgbaImage,
+        color_space: ColorSpace,
+    },
+    Mesh { model: u64, index: usize, data: MeshData },
+}
+
+impl Upload {
+    fn size(&self) -> usize {
+        match self {
+            Upload::Texture { image, .. } => image.as_raw().len(),
+            Upload::Mesh { data, .. } => data.size(),
+        }
+    }
+}
+
+/// A material waiting for its textures to load.
+struct PendingMaterial {
+    id: u64,
+    name: String,
+    textures: MaterialTextures,
+    factors: MaterialFactors,
+}
+
+/// A model some of whose meshes still have to be uploaded.
+struct PendingModel {
+    meshes: Vec<Option<Mesh>>,
+    materials: Vec<Handle<Material>>,
+}
+
+struct Placeholders {
+    texture: Handle<Texture>,
+    /// Stands in for material maps a material doesn't have, white is neutral
+    /// for all of them but the normal map.
+    white: Handle<Texture>,
+    flat_normal: Handle<Texture>,
+    material: Handle<Material>,
+    model: Handle<Model>,
+}
+
+/// Loads textures, materials and models once and hands out shared handles to them.
+///
+/// Files are read and decoded on loader threads. [`AssetServer::update`] picks up
+/// the results and uploads them a few at a time, so loading never stalls a frame.
+/// Assets are keyed by the file they were loaded from, so asking for the same path
+/// twice returns the same GPU resources.
+pub struct AssetServer {
+    textures: Assets<Texture>,
+    materials: Assets<Material>,
+    models: Assets<Model>,
+    next_id: u64,
+    loader: Loader,
+    /// Jobs sent to the loader that haven't come back yet.
+    in_flight: usize,
+    uploads: VecDeque<Upload>,
+    pending_materials: Vec<PendingMaterial>,
+    pending_models: HashMap<u64, PendingModel>,
+    /// Models using each material library, to reload them when it changes.
+    material_libraries: HashMap<PathBuf, HashSet<u64>>,
+    placeholders: Placeholders,
+    /// Bytes uploaded per [`AssetServer::update`]. At least one texture or mesh is
+    /// always uploaded, even if it is larger.
+    pub upload_budget: usize,
+}
+
+impl AssetServer {
+    /// `layout` is the bind group layout materials are created with.
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, layout: &wgpu::BindGroupLayout) -> Self {
+        let mut textures = Assets::default();
+        let mut materials = Assets::default();
+        let mut models = Assets::default();
+
+        let texture = textures.reserve(1, PathBuf::from("<placeholder texture>"));
+        let diffuse_bytes = include_bytes!("../../assets/images/dirt.png");
+        textures.finish(texture.id, Texture::from_bytes(device, queue, diffuse_bytes, "dirt.png"));
+
+        let mut solid = |id: u64, name: &str, rgba: [u8; 4]| {
+            let handle = textures.reserve(id, PathBuf::from(format!("<{} texture>", name)));
+            let image = image::RgbaImage::from_pixel(1, 1, image::Rgba(rgba));
+            textures.finish(handle.id, Texture::from_rgba(device, queue, &image, ColorSpace::Linear, Some(name)));
+            handle
+        };
+        let white = solid(2, "white", [255; 4]);
+        let flat_normal = solid(3, "flat normal", [128, 128, 255, 255]);
+
+        let material = materials.reserve(4, PathBuf::from("<placeholder material>"));
+        let placeholder_textures = MaterialTextures {
+            base_color: texture.clone(),
+            metallic_roughness: white.clone(),
+            normal: flat_normal.clone(),
+            occlusion: white.clone(),
+            emissive: white.clone(),
+        };
+        let placeholder_material = Material::new(
+            device,
+            "placeholder".to_string(),
+            placeholder_textures,
+            MaterialFactors::default(),
+            &textures,
+            layout,
+        );
+        materials.finish(material.id, placeholder_material);
+
+        let model = models.reserve(5, PathBuf::from("<placeholder model>"));
+        models.finish(model.id, Self::placeholder_model(device));
+
+        Self {
+            textures,
+            materials,
+            models,
+            next_id: 5,
+            loader: Loader::with_default_threads(),
+            in_flight: 0,
+            uploads: VecDeque::new(),
+            pending_materials: Vec::new(),
+            pending_models: HashMap::new(),
+            material_libraries: HashMap::new(),
+            placeholders: Placeholders {
+                texture,
+                white,
+                flat_normal,
+                material,
+                model,
+            },
+            upload_budget: DEFAULT_UPLOAD_BUDGET,
+        }
+    }
+
+    /// A cube without materials, so it is drawn with the placeholder material.
+    fn placeholder_model(device: &wgpu::Device) -> anyhow::Result<Model> {
+        let obj = include_str!("../../assets/cube.obj");
+        let (models, _) = tobj::load_obj_buf(
+            &mut BufReader::new(Cursor::new(obj)),
+            &tobj::LoadOptions {
+                triangulate: true,
+                single_index: true,
+                ..Default::default()
+            },
+            |_| Err(tobj::LoadError::OpenFileFailed),
+        )?;
+        let meshes = MeshData::from_obj(&models)
+            .iter()
+            .map(|data| data.upload("placeholder", device))
+            .collect();
+        Ok(Model { meshes, materials: Vec::new() })
+    }
+
+    pub fn get<T: Asset>(&self, handle: &Handle<T>) -> Option<&T> {
+        T::assets(self).get(handle)
+    }
+
+    /// Returns the asset, or its placeholder while it is loading or if it failed.
+    pub fn get_or_placeholder<T: Asset>(&self, handle: &Handle<T>) -> &T {
+        self.get(handle).unwrap_or_else(|| self.placeholder())
+    }
+
+    pub fn placeholder<T: Asset>(&self) -> &T {
+        self.get(T::placeholder(self)).expect("Placeholders are always loaded")
+    }
+
+    pub fn load_state<T: Asset>(&self, handle: &Handle<T>) -> LoadState {
+        T::assets(self).load_state(handle)
+    }
+
+    /// Whether anything requested is still being read, decoded or uploaded.
+    pub fn is_loading(&self) -> bool {
+        self.in_flight > 0
+            || !self.uploads.is_empty()
+            || !self.pending_materials.is_empty()
+            || !self.pending_models.is_empty()
+    }
+
+    /// Files that can be found are keyed by their canonical location, so different
+    /// spellings of the same path share one asset.
+    fn key(file_name: &str) -> PathBuf {
+        match resources::resolve(file_name) {
+            Ok(path) => path.canonicalize().unwrap_or(path),
+            Err(_) => PathBuf::from(file_name),
+        }
+    }
+
+    fn next_id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Textures are keyed by the color space they are read in too, an image
+    /// used both ways is uploaded twice.
+    fn texture_key(key: PathBuf, color_space: ColorSpace) -> PathBuf {
+        match color_space {
+            ColorSpace::Srgb => key,
+            ColorSpace::Linear => {
+                let mut key = key.into_os_string();
+                key.push("#linear");
+                PathBuf::from(key)
+            }
+        }
+    }
+
+    /// Starts loading a texture in the background.
+    pub fn load_texture(&mut self, file_name: &str, color_space: ColorSpace) -> Handle<Texture> {
+        let key = Self::texture_key(Self::key(file_name), color_space);
+        let handle = match self.textures.find(&key) {
+            Some((handle, false)) => return handle,
+            Some((handle, true)) => handle,
+            None => {
+                let id = self.next_id();
+                self.textures.reserve(id, key)
+            }
+        };
+        let id = handle.id;
+        self.request(Job::Texture {
+            id,
+            file_name: file_name.to_string(),
+            color_space,
+        });
+        handle
+    }
+
+    /// A texture decoded from inside `model_file`, uploaded as it is. With
+    /// `refresh` a texture that is already loaded is replaced, as the model was
+    /// reloaded.
+    fn embedded_texture(
+        &mut self,
+        model_file: &str,
+        index: usize,
+        image: &image::RgbaImage,
+        color_space: ColorSpace,
+        refresh: bool,
+    ) -> Handle<Texture> {
+        let mut key = Self::key(model_file).into_os_string();
+        key.push(format!("#image{}", index));
+        let key = Self::texture_key(PathBuf::from(key), color_space);
+        let handle = match self.textures.find(&key) {
+            Some((handle, failed)) if !refresh && !failed => return handle,
+            Some((handle, _)) => handle,
+            None => {
+                let id = self.next_id();
+                self.textures.reserve(id, key)
+            }
+        };
+        self.uploads.push_back(Upload::Texture {
+            id: handle.id,
+            image: image.clone(),
+            color_space,
+        });
+        handle
+    }
+
+    /// Starts loading an OBJ model and its materials in the background.
+    pub fn load_model(&mut self, file_name: &str) -> Handle<Model> {
+        let key = Self::key(file_name);
+        let handle = match self.models.find(&key) {
+            Some((handle, false)) => return handle,
+            Some((handle, true)) => handle,
+            None => {
+                let id = self.next_id();
+                self.models.reserve(id, key)
+            }
+        };
+        let id = handle.id;
+        self.request(Job::Model {
+            id,
+            file_name: file_name.to_string(),
+        });
+        handle
+    }
+
+    /// Loads the assets read from `path` again, after the file changed. Models are
+    /// reloaded when their material library changes. Returns whether any asset
+    /// uses the file.
+    pub fn reload(&mut self, path: &Path) -> bool {
+        let file_name = path.to_string_lossy().into_owned();
+        let mut reloaded = false;
+
+        for color_space in [ColorSpace::Srgb, ColorSpace::Linear] {
+            if let Some(id) = self.textures.id_of(&Self::texture_key(path.to_path_buf(), color_space)) {
+                log::info!("Reloading texture {:?}", path);
+                self.request(Job::Texture {
+                    id,
+                    file_name: file_name.clone(),
+                    color_space,
+                });
+                reloaded = true;
+            }
+        }
+        let mut models = self.models.id_of(path).into_iter().collect::<HashSet<_>>();
+        models.extend(self.material_libraries.get(path).into_iter().flatten());
+        for id in models {
+            if let Some(key) = self.models.entries.get(&id).map(|entry| entry.key.clone()) {
+                log::info!("Reloading model {:?}", key);
+                self.request(Job::Model { id, file_name: key.to_string_lossy().into_owned() });
+                reloaded = true;
+            }
+        }
+        reloaded
+    }
+
+    fn request(&mut self, job: Job) {
+        self.in_flight += 1;
+        self.loader.request(job);
+    }
+
+    /// With `refresh`, materials that are already loaded are created again from
+    /// `material`, as the file they came from changed. `images` are the ones
+    /// embedded in `material_file`.
+    fn load_material(
+        &mut self,
+        material_file: &str,
+        index: usize,
+        material: &MaterialData,
+        images: &[image::RgbaImage],
+        refresh: bool,
+    ) -> Handle<Material> {
+        // Materials are keyed by the file they are defined in and their place in
+        // it, so models sharing an MTL file share the materials too.
+        let mut key = Self::key(material_file).into_os_string();
+        key.push(format!("#{}", index));
+        let key = PathBuf::from(key);
+        let handle = match self.materials.find(&key) {
+            Some((handle, failed)) if !refresh && !failed => return handle,
+            Some((handle, _)) => handle,
+            None => {
+                let id = self.next_id();
+                self.materials.reserve(id, key)
+            }
+        };
+
+        let (white, flat_normal) = (self.placeholders.white.clone(), self.placeholders.flat_normal.clone());
+        let mut texture = |texture: &Option<TextureRef>, color_space: ColorSpace, default: &Handle<Texture>| {
+            match texture {
+                Some(TextureRef::File(file_name)) => self.load_texture(file_name, color_space),
+                Some(TextureRef::Embedded(index)) => match images.get(*index) {
+                    Some(image) => self.embedded_texture(material_file, *index, image, color_space, refresh),
+                    None => default.clone(),
+                },
+                None => default.clone(),
+            }
+        };
+        let textures = MaterialTextures {
+            base_color: texture(&material.base_color_texture, ColorSpace::Srgb, &white),
+            metallic_roughness: texture(&material.metallic_roughness_texture, ColorSpace::Linear, &white),
+            normal: texture(&material.normal_texture, ColorSpace::Linear, &flat_normal),
+            occlusion: texture(&material.occlusion_texture, ColorSpace::Linear, &white),
+            emissive: texture(&material.emissive_texture, ColorSpace::Srgb, &white),
+        };
+        self.pending_materials.push(PendingMaterial {
+            id: handle.id,
+            name: material.name.clone(),
+            textures,
+            factors: material.factors,
+        });
+        handle
+    }
+
+    /// Picks up what the loader threads finished, uploads as much of it as the
+    /// budget allows and unloads assets that are no longer used. Call once a frame.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, layout: &wgpu::BindGroupLayout) {
+        while let Some(loaded) = self.loader.try_recv() {
+            self.receive(loaded);
+        }
+        self.upload(device, queue, self.upload_budget);
+        self.create_materials(device, layout);
+        self.collect_unused();
+    }
+
+    /// Blocks until everything requested so far has loaded, ignoring the upload
+    /// budget. Used when frames have to show the real assets from the start.
+    pub fn finish_loading(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, layout: &wgpu::BindGroupLayout) {
+        loop {
+            while let Some(loaded) = self.loader.try_recv() {
+                self.receive(loaded);
+            }
+            self.upload(device, queue, usize::MAX);
+            self.create_materials(device, layout);
+            if !self.is_loading() {
+                break;
+            }
+            match self.loader.recv() {
+                Some(loaded) => self.receive(loaded),
+                None => break,
+            }
+        }
+    }
+
+    fn receive(&mut self, loaded: Loaded) {
+        self.in_flight -= 1;
+        match loaded {
+            Loaded::Texture { id, color_space, result } => match result {
+                Ok(image) => self.uploads.push_back(Upload::Texture { id, image, color_space }),
+                Err(e) => self.textures.finish(id, Err(e)),
+            },
+            Loaded::Model { id, result } => match result {
+                Ok(obj) if self.models.contains(id) => self.receive_model(id, obj),
+                // Nobody wants the model anymore.
+                Ok(_) => {}
+                Err(e) => self.models.finish(id, Err(e)),
+            },
+        }
+    }
+
+    fn receive_model(&mut self, id: u64, obj: ModelData) {
+        // A model that is already loaded is being reloaded, maybe because its
+        // materials changed.
+        let refresh = self.models.is_loaded(id);
+        let materials = match &obj.material_file {
+            Some(material_file) => {
+                self.material_libraries.entry(Self::key(material_file)).or_default().insert(id);
+                obj.materials
+                    .iter()
+                    .enumerate()
+                    .map(|(index, m)| self.load_material(material_file, index, m, &obj.images, refresh))
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        if obj.meshes.is_empty() {
+            self.models.finish(id, Ok(Model { meshes: Vec::new(), materials }));
+            return;
+        }
+
+        self.pending_models.insert(id, PendingModel {
+            meshes: obj.meshes.iter().map(|_| None).collect(),
+            materials,
+        });
+        for (index, data) in obj.meshes.into_iter().enumerate() {
+            self.uploads.push_back(Upload::Mesh { model: id, index, data });
+        }
+    }
+
+    fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, budget: usize) {
+        let mut spent = 0usize;
+        while let Some(upload) = self.uploads.front() {
+            if spent > 0 && spent.saturating_add(upload.size()) > budget {
+                break;
+            }
+            spent += upload.size();
+
+            match self.uploads.pop_front().unwrap() {
+                Upload::Texture { id, image, color_space } => {
+                    if self.textures.contains(id) {
+                        let label = self.textures.label(id);
+                        let texture = Texture::from_rgba(device, queue, &image, color_space, Some(&label));
+                        self.textures.finish(id, texture);
+                        self.refresh_materials_using(id);
+                    }
+                }
+                Upload::Mesh { model, index, data } => self.upload_mesh(model, index, data, device),
+            }
+        }
+    }
+
+    fn upload_mesh(&mut self, model: u64, index: usize, data: MeshData, device: &wgpu::Device) {
+        if !self.models.contains(model) {
+            self.pending_models.remove(&model);
+            return;
+        }
+        let Some(pending) = self.pending_models.get_mut(&model) else {
+            return;
+        };
+
+        pending.meshes[index] = Some(data.upload(&self.models.label(model), device));
+        if pending.meshes.iter().all(Option::is_some) {
+            let pending = self.pending_models.remove(&model).unwrap();
+            let meshes = pending.meshes.into_iter().flatten().collect();
+            self.models.finish(model, Ok(Model { meshes, materials: pending.materials }));
+        }
+    }
+
+    /// Bind groups point at the texture they were created with, so materials using
+    /// a reloaded texture have to be created again.
+    fn refresh_materials_using(&mut self, texture: u64) {
+        for (id, entry) in &self.materials.entries {
+            if let Slot::Loaded(material) = &entry.slot {
+                if material.textures.iter().any(|handle| handle.id == texture) {
+                    self.pending_materials.push(PendingMaterial {
+                        id: *id,
+                        name: material.name.clone(),
+                        textures: material.textures.clone(),
+                        factors: material.factors,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Creates the bind groups of materials whose textures finished loading.
+    fn create_materials(&mut self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout) {
+        let textures = &self.textures;
+        let materials = &mut self.materials;
+        self.pending_materials.retain(|pending| {
+            let mut failed = None;
+            for handle in pending.textures.iter() {
+                match textures.load_state(handle) {
+                    LoadState::Loading => return true,
+                    LoadState::Loaded => {}
+                    LoadState::Failed(e) => failed = Some(e),
+                }
+            }
+            let result = match failed {
+                Some(e) => Err(anyhow::anyhow!("A texture failed to load: {}", e)),
+                None => Material::new(
+                    device,
+                    pending.name.clone(),
+                    pending.textures.clone(),
+                    pending.factors,
+                    textures,
+                    layout,
+                ),
+            };
+            materials.finish(pending.id, result);
+            false
+        });
+    }
+
+    /// Unloads every asset that is no longer referenced by any handle.
+    pub fn collect_unused(&mut self) {
+        // Unloading a model releases its materials, which release their textures,
+        // so keep going until nothing else becomes unused.
+        loop {
+            let removed =
+                self.models.collect_unused() + self.materials.collect_unused() + self.textures.collect_unused();
+            if removed == 0 {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str) -> PathBuf {
+        PathBuf::from(name)
+    }
+
+    #[test]
+    fn same_path_shares_one_asset() {
+        let mut assets = Assets::<String>::default();
+        let handle = assets.reserve(1, key("a.png"));
+        assets.finish(handle.id, Ok("a".to_string()));
+
+        let (again, reload) = assets.find(&key("a.png")).unwrap();
+        assert_eq!(again, handle);
+        assert!(!reload);
+        assert_eq!(assets.get(&again).map(String::as_str), Some("a"));
+        assert!(assets.find(&key("b.png")).is_none());
+    }
+
+    #[test]
+    fn collect_unused_drops_assets_without_handles() {
+        let mut assets = Assets::<String>::default();
+        let kept = assets.reserve(1, key("kept.png"));
+        let dropped = assets.reserve(2, key("dropped.png"));
+        let clone = kept.clone();
+        drop(kept);
+        drop(dropped);
+
+        assert_eq!(assets.collect_unused(), 1);
+        assert!(assets.contains(clone.id));
+        assert!(!assets.contains(2));
+        assert!(assets.find(&key("dropped.png")).is_none());
+
+        drop(clone);
+        assert_eq!(assets.collect_unused(), 1);
+        assert!(assets.entries.is_empty() && assets.by_key.is_empty());
+    }
+
+    #[test]
+    fn unused_asset_is_revived_before_collection() {
+        let mut assets = Assets::<String>::default();
+        let handle = assets.reserve(1, key("a.png"));
+        assets.finish(handle.id, Ok("a".to_string()));
+        drop(handle);
+
+        let (revived, reload) = assets.find(&key("a.png")).unwrap();
+        assert!(!reload);
+        assert_eq!(assets.collect_unused(), 0);
+        assert_eq!(assets.get(&revived).map(String::as_str), Some("a"));
+    }
+
+    #[test]
+    fn failed_load_is_retried() {
+        let mut assets = Assets::<String>::default();
+        let handle = assets.reserve(1, key("missing.png"));
+        assets.finish(handle.id, Err(anyhow::anyhow!("not found")));
+        assert_eq!(assets.load_state(&handle), LoadState::Failed("not found".to_string()));
+
+        let (again, reload) = assets.find(&key("missing.png")).unwrap();
+        assert_eq!(again, handle);
+        assert!(reload);
+        assert_eq!(assets.load_state(&handle), LoadState::Loading);
+
+        assets.finish(handle.id, Ok("found".to_string()));
+        assert_eq!(assets.get(&handle).map(String::as_str), Some("found"));
+    }
+
+    #[test]
+    fn reload_keeps_handles_valid() {
+        let mut assets = Assets::<String>::default();
+        let handle = assets.reserve(1, key("a.png"));
+        assets.finish(handle.id, Ok("first".to_string()));
+
+        assets.finish(handle.id, Ok("second".to_string()));
+        assert_eq!(assets.get(&handle).map(String::as_str), Some("second"));
+
+        // A failed reload keeps the version that worked.
+        assets.finish(handle.id, Err(anyhow::anyhow!("broken")));
+        assert_eq!(assets.get(&handle).map(String::as_str), Some("second"));
+        assert_eq!(assets.load_state(&handle), LoadState::Loaded);
+    }
+}
@@ -0,0 +1,281 @@
+//! Loads glTF 2.0 models, both `.gltf` files with their buffers and images next
+//! to them or embedded as data URIs, and binary `.glb` files. Everything is read
+//! through the asset search paths.
+//!
+//! Node transforms are baked into the vertices, as models are drawn without
+//! one. Only the first set of texture coordinates is read. Samplers are ignored,
+//! every map of a material is sampled with the base color texture's sampler,
+//! which repeats like the glTF default.
+
+use anyhow::{bail, Context};
+use base64::Engine;
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, SquareMatrix, Vector3};
+use gltf::texture::WrappingMode;
+
+use crate::render::model_obj::{AlphaMode, MaterialFactors, ModelVertex};
+
+use super::resources::{self, MaterialData, MeshData, ModelData, TextureRef};
+
+pub async fn load_gltf(file_name: &str) -> anyhow::Result<ModelData> {
+    let bytes = resources::load_binary(file_name).await?;
+    let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(&bytes)?;
+
+    let mut blob = blob;
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        let data = match buffer.source() {
+            gltf::buffer::Source::Bin => blob.take().context("The binary chunk of the glTF file is missing")?,
+            gltf::buffer::Source::Uri(uri) => load_uri(file_name, uri)
+                .await
+                .with_context(|| format!("Could not load buffer {}", buffer.index()))?,
+        };
+        if data.len() < buffer.length() {
+            bail!("Buffer {} has {} bytes, expected {}", buffer.index(), data.len(), buffer.length());
+        }
+        buffers.push(data);
+    }
+
+    let mut images = Vec::new();
+    let textures = document
+        .images()
+        .map(|image| {
+            let encoded = match image.source() {
+                gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => {
+                    return Some(TextureRef::File(resources::sibling(file_name, &percent_decode(uri))));
+                }
+                gltf::image::Source::Uri { uri, .. } => decode_data_uri(uri),
+                gltf::image::Source::View { view, .. } => buffers[view.buffer().index()]
+                    .get(view.offset()..view.offset() + view.length())
+                    .map(<[u8]>::to_vec)
+                    .with_context(|| format!("Buffer view {} is out of bounds", view.index())),
+            };
+            // A broken image shouldn't lose the model, the material goes without it.
+            match encoded.and_then(|bytes| Ok(image::load_from_memory(&bytes)?.to_rgba8())) {
+                Ok(decoded) => {
+                    images.push(decoded);
+                    Some(TextureRef::Embedded(images.len() - 1))
+                }
+                Err(e) => {
+                    log::warn!("Could not decode image {} of {:?}: {:#}", image.index(), file_name, e);
+                    None
+                }
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let mut materials = document.materials().map(|m| material(&m, &textures)).collect::<Vec<_>>();
+    let mut meshes = Vec::new();
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+        .context("The glTF file has no scene")?;
+    let mut visitor = Visitor {
+        buffers: &buffers,
+        materials: &mut materials,
+        default_material: None,
+        meshes: &mut meshes,
+    };
+    for node in scene.nodes() {
+        visitor.visit(&node, Matrix4::identity());
+    }
+
+    Ok(ModelData {
+        meshes,
+        materials,
+        images,
+        material_file: Some(file_name.to_string()),
+    })
+}
+
+/// Reads a data URI, or a file relative to the model.
+async fn load_uri(file_name: &str, uri: &str) -> anyhow::Result<Vec<u8>> {
+    if uri.starts_with("data:") {
+        return decode_data_uri(uri);
+    }
+    resources::load_binary(&resources::sibling(file_name, &percent_decode(uri))).await
+}
+
+fn decode_data_uri(uri: &str) -> anyhow::Result<Vec<u8>> {
+    let (_, data) = uri.split_once(";base64,").context("Only base64 data URIs are supported")?;
+    Ok(base64::engine::general_purpose::STANDARD.decode(data)?)
+}
+
+/// Relative URIs escape characters like spaces, the files have them unescaped.
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn material(material: &gltf::Material, textures: &[Option<TextureRef>]) -> MaterialData {
+    let texture = |texture: gltf::Texture, tex_coord: u32| {
+        if tex_coord != 0 {
+            log::warn!(
+                "Material {:?} uses texture coordinates {}, reading the first set instead",
+                material.name(),
+                tex_coord
+            );
+        }
+        let sampler = texture.sampler();
+        if (sampler.wrap_s(), sampler.wrap_t()) != (WrappingMode::Repeat, WrappingMode::Repeat) {
+            log::warn!("Material {:?} doesn't repeat its textures, they are repeated anyway", material.name());
+        }
+        textures.get(texture.source().index()).cloned().flatten()
+    };
+    let pbr = material.pbr_metallic_roughness();
+    let normal = material.normal_texture();
+    let occlusion = material.occlusion_texture();
+
+    MaterialData {
+        name: match (material.index(), material.name()) {
+            (_, Some(name)) => name.to_string(),
+            (Some(index), None) => format!("material {}", index),
+            (None, None) => "default".to_string(),
+        },
+        factors: MaterialFactors {
+            base_color: pbr.base_color_factor(),
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            emissive: material.emissive_factor(),
+            normal_scale: normal.as_ref().map_or(1.0, |normal| normal.scale()),
+            occlusion_strength: occlusion.as_ref().map_or(1.0, |occlusion| occlusion.strength()),
+            alpha_mode: match material.alpha_mode() {
+                gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+                gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+            },
+            alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+        },
+        base_color_texture: pbr.base_color_texture().and_then(|info| texture(info.texture(), info.tex_coord())),
+        metallic_roughness_texture: pbr
+            .metallic_roughness_texture()
+            .and_then(|info| texture(info.texture(), info.tex_coord())),
+        normal_texture: normal.and_then(|normal| texture(normal.texture(), normal.tex_coord())),
+        occlusion_texture: occlusion.and_then(|occlusion| texture(occlusion.texture(), occlusion.tex_coord())),
+        emissive_texture: material.emissive_texture().and_then(|info| texture(info.texture(), info.tex_coord())),
+    }
+}
+
+/// Walks the node hierarchy, collecting the meshes with their transforms
+/// applied.
+struct Visitor<'a> {
+    buffers: &'a [Vec<u8>],
+    materials: &'a mut Vec<MaterialData>,
+    /// Index of the default material in `materials`, added once a primitive
+    /// without a material needs it.
+    default_material: Option<usize>,
+    meshes: &'a mut Vec<MeshData>,
+}
+
+impl Visitor<'_> {
+    fn visit(&mut self, node: &gltf::Node, parent: Matrix4<f32>) {
+        let transform = parent * Matrix4::from(node.transform().matrix());
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                match self.primitive(&primitive, transform) {
+                    Some(data) => self.meshes.push(data),
+                    None => log::warn!(
+                        "Skipping primitive {} of mesh {:?}, only triangles with positions are drawn",
+                        primitive.index(),
+                        mesh.name().unwrap_or_default()
+                    ),
+                }
+            }
+        }
+        for child in node.children() {
+            self.visit(&child, transform);
+        }
+    }
+
+    fn primitive(&mut self, primitive: &gltf::Primitive, transform: Matrix4<f32>) -> Option<MeshData> {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            return None;
+        }
+        let reader = primitive.reader(|buffer| self.buffers.get(buffer.index()).map(Vec::as_slice));
+        let positions = reader.read_positions()?;
+        let mut normals = reader.read_normals();
+        let mut tex_coords = reader.read_tex_coords(0).map(|t| t.into_f32());
+
+        let linear = Matrix3::from_cols(transform.x.truncate(), transform.y.truncate(), transform.z.truncate());
+        let normal_matrix = linear.invert().map_or(linear, |inverse| inverse.transpose());
+        let vertices = positions
+            .map(|position| {
+                let normal = normals.as_mut().and_then(Iterator::next).map_or([0.0; 3], |normal| {
+                    // Missing normals stay zero, the shader falls back to the
+                    // face normal for those.
+                    let normal = normal_matrix * Vector3::from(normal);
+                    if normal.magnitude2() > 0.0 {
+                        normal.normalize().into()
+                    } else {
+                        [0.0; 3]
+                    }
+                });
+                ModelVertex {
+                    position: (transform * Vector3::from(position).extend(1.0)).truncate().into(),
+                    tex_coords: tex_coords.as_mut().and_then(Iterator::next).unwrap_or([0.0; 2]),
+                    normal,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut indices = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+            None => (0..vertices.len() as u32).collect(),
+        };
+        // Mirroring transforms turn the triangles inside out.
+        if linear.determinant() < 0.0 {
+            for triangle in indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+
+        let material = match primitive.material().index() {
+            Some(index) => index,
+            None => *self.default_material.get_or_insert_with(|| {
+                self.materials.push(material(&primitive.material(), &[]));
+                self.materials.len() - 1
+            }),
+        };
+        Some(MeshData {
+            vertices,
+            indices,
+            material,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percent_decode_unescapes() {
+        assert_eq!(percent_decode("textures/base%20color.png"), "textures/base color.png");
+        assert_eq!(percent_decode("caf%C3%A9.png"), "café.png");
+        assert_eq!(percent_decode("plain.png"), "plain.png");
+    }
+
+    #[test]
+    fn percent_decode_keeps_broken_escapes() {
+        assert_eq!(percent_decode("100%.png"), "100%.png");
+        assert_eq!(percent_decode("a%zzb"), "a%zzb");
+        assert_eq!(percent_decode("end%2"), "end%2");
+    }
+}
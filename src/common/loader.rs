@@ -0,0 +1,102 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::render::texture::ColorSpace;
+
+use super::resources::{self, ModelData};
+
+/// Work for the loader threads, `id` is the asset it is for.
+pub enum Job {
+    Texture {
+        id: u64,
+        file_name: String,
+        color_space: ColorSpace,
+    },
+    Model {
+        id: u64,
+        file_name: String,
+    },
+}
+
+/// What a [`Job`] turned into.
+pub enum Loaded {
+    Texture {
+        id: u64,
+        color_space: ColorSpace,
+        result: anyhow::Result<image::RgbaImage>,
+    },
+    Model {
+        id: u64,
+        result: anyhow::Result<ModelData>,
+    },
+}
+
+/// A pool of threads that read and decode asset files, so the render thread only
+/// has to upload the results.
+pub struct Loader {
+    jobs: Sender<Job>,
+    results: Receiver<Loaded>,
+}
+
+impl Loader {
+    pub fn new(threads: usize) -> Self {
+        let (jobs, job_receiver) = mpsc::channel::<Job>();
+        let (result_sender, results) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        for i in 0..threads.max(1) {
+            let job_receiver = job_receiver.clone();
+            let result_sender = result_sender.clone();
+            thread::Builder::new()
+                .name(format!("asset loader {}", i))
+                .spawn(move || loop {
+                    // The lock is only held while waiting, never while loading.
+                    let job = job_receiver.lock().unwrap().recv();
+                    // The loader was dropped, nothing is waiting for results anymore.
+                    let Ok(job) = job else { break };
+                    if result_sender.send(Self::load(job)).is_err() {
+                        break;
+                    }
+                })
+                .expect("Could not spawn asset loader thread");
+        }
+
+        Self { jobs, results }
+    }
+
+    /// Uses a few threads, IO bound work doesn't benefit from many more.
+    pub fn with_default_threads() -> Self {
+        Self::new(thread::available_parallelism().map_or(2, |n| n.get().min(4)))
+    }
+
+    pub fn request(&self, job: Job) {
+        // The threads only stop once `self.jobs` is dropped, so this can't fail.
+        self.jobs.send(job).unwrap();
+    }
+
+    /// Returns a finished job, if there is one.
+    pub fn try_recv(&self) -> Option<Loaded> {
+        self.results.try_recv().ok()
+    }
+
+    /// Waits for the next finished job.
+    pub fn recv(&self) -> Option<Loaded> {
+        self.results.recv().ok()
+    }
+
+    fn load(job: Job) -> Loaded {
+        match job {
+            Job::Texture { id, file_name, color_space } => Loaded::Texture {
+                id,
+                color_space,
+                result: pollster::block_on(resources::load_binary(&file_name))
+                    .and_then(|data| Ok(image::load_from_memory(&data)?.to_rgba8())),
+            },
+            Job::Model { id, file_name } => Loaded::Model {
+                id,
+                result: pollster::block_on(resources::load_model(&file_name)),
+            },
+        }
+    }
+}
@@ -1 +1,7 @@
-pub mod resources;
\ No newline at end of file
+pub mod assets;
+pub mod gltf_model;
+pub mod loader;
+pub mod pacing;
+pub mod resources;
+pub mod timestep;
+pub mod watcher;
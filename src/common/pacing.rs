@@ -0,0 +1,162 @@
+use instant::{Duration, Instant};
+
+/// What the event loop should do after handling the current batch of events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pacing {
+    /// Draw a frame now.
+    Draw,
+    /// Sleep until the given time, then ask again.
+    WaitUntil(Instant),
+    /// Nothing to draw, sleep until the next event arrives.
+    Wait,
+}
+
+/// Decides when frames are drawn, either capped to a maximum rate or, in low
+/// power mode, only when something on screen changes.
+pub struct FramePacer {
+    frame_time: Option<Duration>,
+    low_power: bool,
+    next_frame: Instant,
+    last_frame: Instant,
+    /// Set by input and resizes, so low power mode draws at least one more frame.
+    dirty: bool,
+    /// Whether the last poll let the loop go to sleep, so the time spent idle
+    /// isn't handed to the simulation on wake up.
+    idle: bool,
+}
+
+impl FramePacer {
+    pub fn new(fps_cap: Option<u32>, low_power: bool) -> Self {
+        Self::starting_at(fps_cap, low_power, Instant::now())
+    }
+
+    fn starting_at(fps_cap: Option<u32>, low_power: bool, now: Instant) -> Self {
+        Self {
+            frame_time: fps_cap
+                .filter(|fps| *fps > 0)
+                .map(|fps| Duration::from_secs_f64(1.0 / fps as f64)),
+            low_power,
+            next_frame: now,
+            last_frame: now,
+            dirty: true,
+            idle: false,
+        }
+    }
+
+    pub fn request_redraw(&mut self) {
+        self.dirty = true;
+    }
+
+    pub fn poll(&mut self, now: Instant, animating: bool) -> Pacing {
+        if self.low_power && !animating && !self.dirty {
+            self.idle = true;
+            return Pacing::Wait;
+        }
+
+        if let Some(frame_time) = self.frame_time {
+            if now < self.next_frame {
+                return Pacing::WaitUntil(self.next_frame);
+            }
+            // Schedule from the previous deadline to keep a steady rate, unless we
+            // fell behind, in which case start over from now.
+            self.next_frame += frame_time;
+            if self.next_frame < now {
+                self.next_frame = now + frame_time;
+            }
+        }
+
+        self.dirty = false;
+        Pacing::Draw
+    }
+
+    /// Returns the time since the last frame, which is zero right after waking up
+    /// from low power mode.
+    pub fn frame_dt(&mut self, now: Instant) -> Duration {
+        let dt = if std::mem::take(&mut self.idle) {
+            Duration::ZERO
+        } else {
+            now - self.last_frame
+        };
+        self.last_frame = now;
+        dt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn uncapped_always_draws() {
+        let start = Instant::now();
+        let mut pacer = FramePacer::starting_at(None, false, start);
+
+        assert_eq!(pacer.poll(start, false), Pacing::Draw);
+        assert_eq!(pacer.poll(start, false), Pacing::Draw);
+        assert_eq!(pacer.poll(start + ms(1), true), Pacing::Draw);
+    }
+
+    #[test]
+    fn capped_waits_until_next_frame() {
+        let start = Instant::now();
+        let mut pacer = FramePacer::starting_at(Some(100), false, start);
+
+        assert_eq!(pacer.poll(start, true), Pacing::Draw);
+        assert_eq!(pacer.poll(start + ms(4), true), Pacing::WaitUntil(start + ms(10)));
+        assert_eq!(pacer.poll(start + ms(10), true), Pacing::Draw);
+        assert_eq!(pacer.poll(start + ms(12), true), Pacing::WaitUntil(start + ms(20)));
+    }
+
+    #[test]
+    fn capped_restarts_schedule_after_falling_behind() {
+        let start = Instant::now();
+        let mut pacer = FramePacer::starting_at(Some(100), false, start);
+
+        assert_eq!(pacer.poll(start, true), Pacing::Draw);
+        assert_eq!(pacer.poll(start + ms(55), true), Pacing::Draw);
+        // Rather than drawing the missed frames back to back.
+        assert_eq!(pacer.poll(start + ms(58), true), Pacing::WaitUntil(start + ms(65)));
+    }
+
+    #[test]
+    fn low_power_waits_once_clean() {
+        let start = Instant::now();
+        let mut pacer = FramePacer::starting_at(None, true, start);
+
+        // The first frame is always drawn.
+        assert_eq!(pacer.poll(start, false), Pacing::Draw);
+        assert_eq!(pacer.poll(start + ms(1), false), Pacing::Wait);
+        assert_eq!(pacer.poll(start + ms(2), true), Pacing::Draw);
+        assert_eq!(pacer.poll(start + ms(3), true), Pacing::Draw);
+    }
+
+    #[test]
+    fn low_power_redraws_after_input() {
+        let start = Instant::now();
+        let mut pacer = FramePacer::starting_at(None, true, start);
+        pacer.poll(start, false);
+        assert_eq!(pacer.poll(start + ms(1), false), Pacing::Wait);
+
+        pacer.request_redraw();
+        assert_eq!(pacer.poll(start + ms(2), false), Pacing::Draw);
+        assert_eq!(pacer.poll(start + ms(3), false), Pacing::Wait);
+    }
+
+    #[test]
+    fn frame_dt_is_zero_after_idle() {
+        let start = Instant::now();
+        let mut pacer = FramePacer::starting_at(None, true, start);
+        pacer.poll(start, false);
+        assert_eq!(pacer.frame_dt(start + ms(16)), ms(16));
+
+        assert_eq!(pacer.poll(start + ms(20), false), Pacing::Wait);
+        pacer.request_redraw();
+        assert_eq!(pacer.poll(start + ms(5000), false), Pacing::Draw);
+        assert_eq!(pacer.frame_dt(start + ms(5000)), Duration::ZERO);
+        assert_eq!(pacer.frame_dt(start + ms(5016)), ms(16));
+    }
+}
@@ -1,9 +1,14 @@
+use std::cell::RefCell;
 use std::io::{BufReader, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 use cfg_if::cfg_if;
 use wgpu::util::DeviceExt;
 
-use crate::{render::model_obj as model, render::texture};
+use crate::render::model_obj::{self as model, AlphaMode, MaterialFactors};
+
+use super::gltf_model;
 
 
 //commenteded because is for wasm
@@ -19,6 +24,77 @@ use crate::{render::model_obj as model, render::texture};
 //     base.join(file_name).unwrap()
 // }
 
+/// Environment variable with extra asset folders, separated like `PATH`.
+pub const ASSETS_ENV_VAR: &str = "RUST_GRAPHICS_ASSETS";
+
+/// Folders assets are looked up in, highest priority first.
+static ASSET_PATHS: OnceLock<Vec<PathBuf>> = OnceLock::new();
+
+/// Sets up the asset search paths. `extra` folders (e.g. from the command line) are
+/// searched first, then the ones in [`ASSETS_ENV_VAR`], then the `assets` folder
+/// next to the executable and, in debug builds, the one in the source tree.
+///
+/// Has to be called before the first asset is loaded to have an effect.
+pub fn init_asset_paths(extra: &[PathBuf]) {
+    if ASSET_PATHS.set(default_asset_paths(extra)).is_err() {
+        log::warn!("Asset paths were already initialized, ignoring {:?}", extra);
+    }
+}
+
+fn default_asset_paths(extra: &[PathBuf]) -> Vec<PathBuf> {
+    let mut paths = extra.to_vec();
+    if let Some(env_paths) = std::env::var_os(ASSETS_ENV_VAR) {
+        paths.extend(std::env::split_paths(&env_paths).filter(|p| !p.as_os_str().is_empty()));
+    }
+    if let Some(exe_dir) = std::env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf)) {
+        paths.push(exe_dir.join("assets"));
+    }
+    if cfg!(debug_assertions) {
+        paths.push(Path::new(env!("CARGO_MANIFEST_DIR")).join("assets"));
+    }
+    paths
+}
+
+pub fn asset_paths() -> &'static [PathBuf] {
+    ASSET_PATHS.get_or_init(|| default_asset_paths(&[]))
+}
+
+/// Returned when an asset is in none of the search paths.
+#[derive(Debug)]
+pub struct AssetNotFound {
+    pub file_name: String,
+    pub searched: Vec<PathBuf>,
+}
+
+impl std::fmt::Display for AssetNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Asset {:?} not found, searched:", self.file_name)?;
+        for path in &self.searched {
+            write!(f, "\n  {}", path.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AssetNotFound {}
+
+/// Finds the file an asset name refers to. Absolute paths are used as they are.
+pub fn resolve(file_name: &str) -> Result<PathBuf, AssetNotFound> {
+    let searched = if Path::new(file_name).is_absolute() {
+        vec![PathBuf::from(file_name)]
+    } else {
+        asset_paths().iter().map(|root| root.join(file_name)).collect()
+    };
+
+    match searched.iter().find(|path| path.is_file()) {
+        Some(path) => Ok(path.clone()),
+        None => Err(AssetNotFound {
+            file_name: file_name.to_string(),
+            searched,
+        }),
+    }
+}
+
 pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
     cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
@@ -28,10 +104,7 @@ pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
                 .text()
                 .await?;
         } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("assets")
-                .join(file_name);
-            let txt = std::fs::read_to_string(path)?;
+            let txt = std::fs::read_to_string(resolve(file_name)?)?;
         }
     }
 
@@ -48,10 +121,7 @@ pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
                 .await?
                 .to_vec();
         } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("assets")
-                .join(file_name);
-            let data = std::fs::read(path)?;
+            let data = std::fs::read(resolve(file_name)?)?;
         }
     }
 
@@ -59,26 +129,29 @@ pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
 }
 
 
-pub async fn load_texture(
-    file_name: &str,
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-) -> anyhow::Result<texture::Texture> {
-    let data = load_binary(file_name).await?;
-    texture::Texture::from_bytes(device, queue, &data, file_name)
+/// Resolves `other` relative to the folder `file_name` is in, so models can
+/// reference their materials and textures with relative paths.
+pub fn sibling(file_name: &str, other: &str) -> String {
+    match Path::new(file_name).parent() {
+        Some(parent) => parent.join(other).to_string_lossy().into_owned(),
+        None => other.to_string(),
+    }
 }
 
+/// An OBJ file parsed on the CPU, before anything is uploaded to the GPU.
+pub struct ObjData {
+    pub models: Vec<tobj::Model>,
+    pub materials: Vec<tobj::Material>,
+    /// The material library the materials were read from, if any.
+    pub mtl_file: Option<String>,
+}
 
-pub async fn load_model(
-    file_name: &str,
-    device: &wgpu::Device,
-    queue: &wgpu::Queue,
-    layout: &wgpu::BindGroupLayout,
-) -> anyhow::Result<model::Model> {
+pub async fn load_obj(file_name: &str) -> anyhow::Result<ObjData> {
     let obj_text = load_string(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
     let mut obj_reader = BufReader::new(obj_cursor);
 
+    let mtl_files = RefCell::new(Vec::new());
     let (models, obj_materials) = tobj::load_obj_buf_async(
         &mut obj_reader,
         &tobj::LoadOptions {
@@ -86,88 +159,337 @@ pub async fn load_model(
             single_index: true,
             ..Default::default()
         },
-        |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+        |p| {
+            let mtl_file = sibling(file_name, &p);
+            mtl_files.borrow_mut().push(mtl_file.clone());
+            async move {
+                match load_string(&mtl_file).await {
+                    Ok(mat_text) => tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text))),
+                    Err(e) => {
+                        log::warn!("Could not load material library {:?}: {:?}", mtl_file, e);
+                        Err(tobj::LoadError::OpenFileFailed)
+                    }
+                }
+            }
         },
     )
     .await?;
 
-    let mut materials = Vec::new();
-    for m in obj_materials? {
-        let diffuse_texture = load_texture(&m.diffuse_texture, device, queue).await?;
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: None,
+    Ok(ObjData {
+        models,
+        // A broken material library shouldn't lose the geometry, the meshes are
+        // drawn with the default material instead.
+        materials: obj_materials.unwrap_or_default(),
+        mtl_file: mtl_files.into_inner().into_iter().next(),
+    })
+}
+
+/// A color parameter of an MTL material that tobj leaves unparsed, like `Ke`.
+/// `None` if the material has none or it can't be read.
+fn mtl_color(material: &tobj::Material, key: &str) -> Option<[f32; 3]> {
+    let value = material.unknown_param.get(key)?;
+    let channels = value.split_whitespace().map(str::parse::<f32>).collect::<Result<Vec<_>, _>>();
+    match channels.as_deref() {
+        Ok(&[r, g, b]) => Some([r, g, b]),
+        // A single value is used for all channels, like for Kd.
+        Ok(&[v]) => Some([v; 3]),
+        _ => {
+            log::warn!("Ignoring unreadable {} {:?} of material {:?}", key, value, material.name);
+            None
+        }
+    }
+}
+
+/// A single number parameter of an MTL material that tobj leaves unparsed.
+fn mtl_scalar(material: &tobj::Material, key: &str) -> Option<f32> {
+    let value = material.unknown_param.get(key)?;
+    match value.trim().parse::<f32>() {
+        Ok(value) => Some(value),
+        Err(_) => {
+            log::warn!("Ignoring unreadable {} {:?} of material {:?}", key, value, material.name);
+            None
+        }
+    }
+}
+
+/// Perceived brightness of a linear color.
+fn luminance([r, g, b]: [f32; 3]) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+/// Where a material texture is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextureRef {
+    /// A file, found like any other asset.
+    File(String),
+    /// One of [`ModelData::images`].
+    Embedded(usize),
+}
+
+/// A material as a model file describes it, before its textures are loaded.
+#[derive(Debug, Clone, Default)]
+pub struct MaterialData {
+    pub name: String,
+    pub factors: MaterialFactors,
+    pub base_color_texture: Option<TextureRef>,
+    pub metallic_roughness_texture: Option<TextureRef>,
+    pub normal_texture: Option<TextureRef>,
+    pub occlusion_texture: Option<TextureRef>,
+    pub emissive_texture: Option<TextureRef>,
+}
+
+impl MaterialData {
+    /// Maps a Blinn-Phong MTL material onto metallic-roughness, with textures
+    /// relative to `mtl_file`. The `Pm` and `Pr` parameters of the PBR extension
+    /// to MTL are used as they are, otherwise:
+    ///
+    /// - `Kd` is the base color, unless `map_Kd` is there, which exporters pair
+    ///   with all kinds of `Kd`.
+    /// - `Ns` is turned into the roughness of a GGX lobe about as wide as the
+    ///   Blinn-Phong one.
+    /// - A `Ks` brighter than `Kd` is taken to be a metal, the more so the
+    ///   darker the diffuse color. Dielectrics reflect about the same small
+    ///   amount whatever their `Ks`, which exporters rarely get right.
+    /// - `map_Bump`, or `norm`, is taken to be a normal map, `Ke` and `map_Ke`
+    ///   are the emission and `d` the alpha.
+    pub fn from_mtl(mtl_file: &str, material: &tobj::Material) -> Self {
+        let texture = |name: &str| match name.trim() {
+            "" => None,
+            name => Some(TextureRef::File(sibling(mtl_file, name))),
+        };
+        let unknown_texture = |key: &str| material.unknown_param.get(key).and_then(|name| texture(name));
+
+        let base_color_texture = texture(&material.diffuse_texture);
+        let [r, g, b] = match base_color_texture {
+            Some(_) => [1.0; 3],
+            None => material.diffuse,
+        };
+
+        let specular = luminance(material.specular);
+        let diffuse = luminance(material.diffuse);
+        let metallic = mtl_scalar(material, "Pm").unwrap_or_else(|| {
+            if specular > diffuse {
+                (specular - diffuse) / specular
+            } else {
+                0.0
+            }
+        });
+        // The GGX roughness that gives about the same highlight as the
+        // Blinn-Phong exponent `Ns`.
+        let roughness = mtl_scalar(material, "Pr")
+            .unwrap_or_else(|| (2.0 / (material.shininess.max(0.0) + 2.0)).powf(0.25));
+        let emissive_texture = unknown_texture("map_Ke");
+        // An alpha map usually cuts out leaves and the like. It is often the
+        // base color texture itself, whose alpha is what gets tested.
+        let alpha_mode = if texture(&material.dissolve_texture).is_some() {
+            AlphaMode::Mask
+        } else if material.dissolve < 1.0 {
+            AlphaMode::Blend
+        } else {
+            AlphaMode::Opaque
+        };
+        let emissive = mtl_color(material, "Ke").unwrap_or(match emissive_texture {
+            Some(_) => [1.0; 3],
+            None => [0.0; 3],
         });
 
-        materials.push(model::Material {
-            name: m.name,
-            diffuse_texture,
-            bind_group,
-        })
-    }
-
-    let meshes = models
-        .into_iter()
-        .map(|m| {
-            println!("amount of positions {:?}", m.mesh.positions.len());
-            println!("amount of texcoords {:?}", m.mesh.texcoords.len());
-            println!("amount of texcoord_indices {:?}", m.mesh.texcoord_indices.len());
-            println!("amount of len indices {:?}", m.mesh.indices.len());
-
-
-            let vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| {
-                    //println!("vertice number {i:?}");
-                    model::ModelVertex {
-                    position: [
-                        m.mesh.positions[i * 3],
-                        m.mesh.positions[i * 3 + 1],
-                        m.mesh.positions[i * 3 + 2],
-                    ],
-                    tex_coords: [m.mesh.texcoords[i * 2], m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
-                }})
-                .collect::<Vec<_>>();
-            // println!("vertices: {vertices:?}");
-            // println!("-----------------");
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-            model::Mesh {
-                name: file_name.to_string(),
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
+        Self {
+            name: material.name.clone(),
+            factors: MaterialFactors {
+                base_color: [r, g, b, material.dissolve],
+                metallic: metallic.clamp(0.0, 1.0),
+                roughness: roughness.clamp(0.0, 1.0),
+                emissive,
+                alpha_mode,
+                ..Default::default()
+            },
+            base_color_texture,
+            metallic_roughness_texture: None,
+            normal_texture: texture(&material.normal_texture).or_else(|| unknown_texture("norm")),
+            occlusion_texture: None,
+            emissive_texture,
+        }
+    }
+}
+
+/// A model file parsed on the CPU, before anything is uploaded to the GPU.
+pub struct ModelData {
+    pub meshes: Vec<MeshData>,
+    pub materials: Vec<MaterialData>,
+    /// Decoded images that were embedded in the model file.
+    pub images: Vec<image::RgbaImage>,
+    /// The file the materials are defined in: the material library of an OBJ
+    /// file, or the model file itself.
+    pub material_file: Option<String>,
+}
+
+impl ModelData {
+    pub fn from_obj(obj: ObjData) -> Self {
+        let materials = match &obj.mtl_file {
+            Some(mtl_file) => obj.materials.iter().map(|m| MaterialData::from_mtl(mtl_file, m)).collect(),
+            None => Vec::new(),
+        };
+        Self {
+            meshes: MeshData::from_obj(&obj.models),
+            materials,
+            images: Vec::new(),
+            material_file: obj.mtl_file,
+        }
+    }
+}
+
+/// Loads an OBJ or, going by the extension, a glTF model.
+pub async fn load_model(file_name: &str) -> anyhow::Result<ModelData> {
+    let extension = Path::new(file_name).extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("gltf" | "glb") => gltf_model::load_gltf(file_name).await,
+        _ => Ok(ModelData::from_obj(load_obj(file_name).await?)),
+    }
+}
+
+/// The vertices and indices of one mesh, ready to be uploaded.
+pub struct MeshData {
+    pub vertices: Vec<model::ModelVertex>,
+    pub indices: Vec<u32>,
+    /// Index into [`ModelData::materials`].
+    pub material: usize,
+}
+
+impl MeshData {
+    pub fn from_obj(models: &[tobj::Model]) -> Vec<Self> {
+        models
+            .iter()
+            .map(|m| {
+                let vertices = (0..m.mesh.positions.len() / 3)
+                    .map(|i| model::ModelVertex {
+                        position: [
+                            m.mesh.positions[i * 3],
+                            m.mesh.positions[i * 3 + 1],
+                            m.mesh.positions[i * 3 + 2],
+                        ],
+                        // Texture coordinates and normals are optional in OBJ files.
+                        tex_coords: match m.mesh.texcoords.get(i * 2..i * 2 + 2) {
+                            Some(uv) => [uv[0], uv[1]],
+                            None => [0.0; 2],
+                        },
+                        normal: match m.mesh.normals.get(i * 3..i * 3 + 3) {
+                            Some(n) => [n[0], n[1], n[2]],
+                            None => [0.0; 3],
+                        },
+                    })
+                    .collect::<Vec<_>>();
+
+                MeshData {
+                    vertices,
+                    indices: m.mesh.indices.clone(),
+                    material: m.mesh.material_id.unwrap_or(0),
+                }
+            })
+            .collect::<Vec<_>>()
+    }
+
+    /// Bytes the mesh takes up on the GPU.
+    pub fn size(&self) -> usize {
+        std::mem::size_of_val(self.vertices.as_slice()) + std::mem::size_of_val(self.indices.as_slice())
+    }
+
+    fn center(&self) -> [f32; 3] {
+        if self.vertices.is_empty() {
+            return [0.0; 3];
+        }
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+        for vertex in &self.vertices {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(vertex.position[axis]);
+                max[axis] = max[axis].max(vertex.position[axis]);
             }
-        })
-        .collect::<Vec<_>>();
+        }
+        std::array::from_fn(|axis| (min[axis] + max[axis]) / 2.0)
+    }
+
+    pub fn upload(&self, name: &str, device: &wgpu::Device) -> model::Mesh {
+        log::debug!("{:?}: {} vertices, {} indices", name, self.vertices.len(), self.indices.len());
 
-    Ok(model::Model { meshes, materials })
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(&self.vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Index Buffer", name)),
+            contents: bytemuck::cast_slice(&self.indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        model::Mesh {
+            name: name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            num_elements: self.indices.len() as u32,
+            material: self.material,
+            center: self.center(),
+        }
+    }
 }
 
- 
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_mtl(mtl: &str) -> MaterialData {
+        let (materials, _) = tobj::load_mtl_buf(&mut mtl.as_bytes()).unwrap();
+        MaterialData::from_mtl("models/test.mtl", &materials[0])
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-5, "expected {}, got {}", expected, actual);
+    }
+
+    #[test]
+    fn specular_brighter_than_diffuse_is_metallic() {
+        let material = from_mtl("newmtl steel\nKd 0.1 0.1 0.1\nKs 0.9 0.9 0.9\n");
+        assert_close(material.factors.metallic, 0.8 / 0.9);
+    }
+
+    #[test]
+    fn specular_darker_than_diffuse_is_dielectric() {
+        let material = from_mtl("newmtl plastic\nKd 0.8 0.8 0.8\nKs 0.5 0.5 0.5\n");
+        assert_close(material.factors.metallic, 0.0);
+    }
+
+    #[test]
+    fn roughness_follows_the_shininess() {
+        assert_close(from_mtl("newmtl matte\nNs 0\n").factors.roughness, 1.0);
+        let shiny = from_mtl("newmtl shiny\nNs 1000\n");
+        assert_close(shiny.factors.roughness, (2.0f32 / 1002.0).powf(0.25));
+    }
+
+    #[test]
+    fn pbr_extensions_override_the_heuristics() {
+        let material = from_mtl("newmtl pbr\nKd 0.1 0.1 0.1\nKs 0.9 0.9 0.9\nNs 1000\nPm 0.25\nPr 0.75\n");
+        assert_close(material.factors.metallic, 0.25);
+        assert_close(material.factors.roughness, 0.75);
+    }
+
+    #[test]
+    fn alpha_mode_comes_from_dissolve() {
+        let opaque = from_mtl("newmtl opaque\nd 1\n");
+        assert_eq!(opaque.factors.alpha_mode, AlphaMode::Opaque);
+
+        let glass = from_mtl("newmtl glass\nd 0.5\n");
+        assert_eq!(glass.factors.alpha_mode, AlphaMode::Blend);
+        assert_close(glass.factors.base_color[3], 0.5);
+
+        // An alpha map cuts out, even when the material is also translucent.
+        let leaf = from_mtl("newmtl leaf\nd 0.5\nmap_d leaf.png\n");
+        assert_eq!(leaf.factors.alpha_mode, AlphaMode::Mask);
+    }
+
+    #[test]
+    fn base_color_texture_replaces_the_diffuse_color() {
+        let material = from_mtl("newmtl wood\nKd 0.5 0.25 0.1\nmap_Kd wood.png\n");
+        assert_eq!(material.base_color_texture, Some(TextureRef::File(sibling("models/test.mtl", "wood.png"))));
+        assert_eq!(material.factors.base_color, [1.0; 4]);
+    }
+}
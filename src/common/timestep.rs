@@ -0,0 +1,110 @@
+use instant::Duration;
+
+/// Splits variable frame times into a whole number of fixed size simulation steps.
+///
+/// Time that doesn't add up to a full step is carried over to the next frame, and
+/// [`FixedTimestep::alpha`] tells how far into the next step the frame is, so
+/// rendering can interpolate between the last two simulated states.
+#[derive(Debug, Clone)]
+pub struct FixedTimestep {
+    step: Duration,
+    max_steps: u32,
+    accumulator: Duration,
+}
+
+impl FixedTimestep {
+    pub fn new(step: Duration, max_steps: u32) -> Self {
+        assert!(!step.is_zero(), "the fixed timestep must be greater than zero");
+        assert!(max_steps >= 1, "at least one step per frame must be allowed");
+        Self {
+            step,
+            max_steps,
+            accumulator: Duration::ZERO,
+        }
+    }
+
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    /// Adds the time that passed since the last frame and returns how many steps
+    /// should be simulated.
+    pub fn advance(&mut self, dt: Duration) -> u32 {
+        self.accumulator += dt;
+
+        let mut steps = 0;
+        while self.accumulator >= self.step {
+            self.accumulator -= self.step;
+            steps += 1;
+
+            // If updates can't keep up, catching up would only make the next frame
+            // slower still. Drop the backlog and let the simulation fall behind.
+            if steps == self.max_steps {
+                self.accumulator = Duration::from_nanos(
+                    (self.accumulator.as_nanos() % self.step.as_nanos()) as u64,
+                );
+                break;
+            }
+        }
+
+        steps
+    }
+
+    /// How far between the previous and the next step the current frame is, in `[0, 1)`.
+    pub fn alpha(&self) -> f32 {
+        self.accumulator.as_secs_f32() / self.step.as_secs_f32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn remainder_carries_over_to_next_frame() {
+        let mut timestep = FixedTimestep::new(ms(10), 5);
+
+        assert_eq!(timestep.advance(ms(15)), 1);
+        assert_eq!(timestep.advance(ms(4)), 0);
+        assert_eq!(timestep.advance(ms(1)), 1);
+        assert_eq!(timestep.advance(ms(25)), 2);
+    }
+
+    #[test]
+    fn steps_are_clamped_to_max_steps() {
+        let mut timestep = FixedTimestep::new(ms(10), 3);
+
+        assert_eq!(timestep.advance(ms(105)), 3);
+        // The backlog is dropped, only the part of a step is kept.
+        assert!((timestep.alpha() - 0.5).abs() < 1e-6);
+        assert_eq!(timestep.advance(ms(5)), 1);
+    }
+
+    #[test]
+    fn alpha_is_fraction_of_next_step() {
+        let mut timestep = FixedTimestep::new(ms(10), 5);
+        assert_eq!(timestep.alpha(), 0.0);
+
+        timestep.advance(ms(12));
+        assert!((timestep.alpha() - 0.2).abs() < 1e-6);
+
+        timestep.advance(ms(5));
+        assert!((timestep.alpha() - 0.7).abs() < 1e-6);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_step_is_rejected() {
+        FixedTimestep::new(Duration::ZERO, 5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn zero_max_steps_is_rejected() {
+        FixedTimestep::new(ms(10), 0);
+    }
+}
@@ -0,0 +1,58 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use super::resources;
+
+/// Watches the asset folders and reports the files that changed in them.
+pub struct AssetWatcher {
+    // Stops watching when dropped.
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<notify::Event>>,
+}
+
+impl AssetWatcher {
+    /// Watches every asset search path that exists.
+    pub fn new() -> anyhow::Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            // The receiver is only gone while the watcher is being dropped.
+            let _ = sender.send(event);
+        })?;
+
+        for path in resources::asset_paths().iter().filter(|path| path.is_dir()) {
+            watcher.watch(path, RecursiveMode::Recursive)?;
+            log::info!("Watching {:?} for changes", path);
+        }
+
+        Ok(Self {
+            _watcher: watcher,
+            events,
+        })
+    }
+
+    /// Returns the files that were created or modified since the last call, each
+    /// once. Editors often write a file several times when saving, so this is
+    /// meant to be called once a frame rather than per event.
+    pub fn changed(&self) -> Vec<PathBuf> {
+        let mut changed = HashSet::new();
+        for event in self.events.try_iter() {
+            match event {
+                Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                    // Paths are compared with resolved asset paths, which are canonical.
+                    changed.extend(event.paths.iter().filter_map(|path| canonical_file(path)));
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("Error watching assets: {:?}", e),
+            }
+        }
+        changed.into_iter().collect()
+    }
+}
+
+/// Files that were removed again or are folders are of no interest.
+fn canonical_file(path: &Path) -> Option<PathBuf> {
+    path.canonicalize().ok().filter(|path| path.is_file())
+}
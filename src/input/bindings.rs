@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use winit::event::{ElementState, ModifiersState, MouseButton, VirtualKeyCode};
+
+use crate::common::resources;
+
+use super::event::InputEvent;
+
+/// Something the user wants to do, independent of the key or button bound to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    Ascend,
+    Descend,
+    /// Rotates the camera with the mouse while held.
+    Look,
+    Quit,
+    ToggleWireframe,
+    /// Switches to the next supported MSAA sample count.
+    CycleMsaa,
+    /// Switches between the post-processing anti-aliasing modes.
+    CyclePostAntiAliasing,
+    /// Turns the glow around bright parts of the scene on or off.
+    ToggleBloom,
+    /// Switches between the tonemapping curves.
+    CycleTonemapper,
+    /// Turns screen-space ambient occlusion on or off.
+    ToggleSsao,
+    /// Switches between the fog modes.
+    CycleFog,
+    /// Switches between sorted and order-independent transparency.
+    CycleTransparency,
+    /// Makes the fog denser, or brings linear fog closer.
+    ThickenFog,
+    /// Makes the fog thinner, or pushes linear fog further away.
+    ThinFog,
+    /// Brightens the image by half a stop.
+    IncreaseExposure,
+    /// Darkens the image by half a stop.
+    DecreaseExposure,
+}
+
+/// A physical key or mouse button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Input {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+/// Modifier keys that must be held for a binding to trigger.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub logo: bool,
+}
+
+impl Modifiers {
+    fn from_state(state: ModifiersState) -> Self {
+        Self {
+            shift: state.shift(),
+            ctrl: state.ctrl(),
+            alt: state.alt(),
+            logo: state.logo(),
+        }
+    }
+
+    /// Whether every modifier required by `other` is also set in `self`.
+    fn contains(&self, other: &Modifiers) -> bool {
+        (self.shift || !other.shift)
+            && (self.ctrl || !other.ctrl)
+            && (self.alt || !other.alt)
+            && (self.logo || !other.logo)
+    }
+
+    fn count(&self) -> usize {
+        [self.shift, self.ctrl, self.alt, self.logo].iter().filter(|m| **m).count()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Binding {
+    pub input: Input,
+    #[serde(default)]
+    pub modifiers: Modifiers,
+}
+
+impl Binding {
+    pub fn key(key: VirtualKeyCode) -> Self {
+        Self { input: Input::Key(key), modifiers: Modifiers::default() }
+    }
+
+    pub fn mouse(button: MouseButton) -> Self {
+        Self { input: Input::Mouse(button), modifiers: Modifiers::default() }
+    }
+}
+
+/// The action to input table, as stored in the bindings config file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputBindings {
+    pub bindings: HashMap<Action, Vec<Binding>>,
+}
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        use VirtualKeyCode as Key;
+
+        let bindings = HashMap::from([
+            (Action::MoveForward, vec![Binding::key(Key::W), Binding::key(Key::Up)]),
+            (Action::MoveBackward, vec![Binding::key(Key::S), Binding::key(Key::Down)]),
+            (Action::MoveLeft, vec![Binding::key(Key::A), Binding::key(Key::Left)]),
+            (Action::MoveRight, vec![Binding::key(Key::D), Binding::key(Key::Right)]),
+            (Action::Ascend, vec![Binding::key(Key::Space)]),
+            (Action::Descend, vec![Binding::key(Key::LShift)]),
+            (Action::Look, vec![Binding::mouse(MouseButton::Left)]),
+            (Action::Quit, vec![Binding::key(Key::Escape)]),
+            (Action::ToggleWireframe, vec![Binding::key(Key::F1)]),
+            (Action::CycleMsaa, vec![Binding::key(Key::F2)]),
+            (Action::CyclePostAntiAliasing, vec![Binding::key(Key::F7)]),
+            (Action::ToggleBloom, vec![Binding::key(Key::F4)]),
+            (Action::CycleTonemapper, vec![Binding::key(Key::F3)]),
+            (Action::ToggleSsao, vec![Binding::key(Key::F5)]),
+            (Action::CycleFog, vec![Binding::key(Key::F6)]),
+            (Action::CycleTransparency, vec![Binding::key(Key::F8)]),
+            (Action::ThickenFog, vec![Binding::key(Key::PageUp)]),
+            (Action::ThinFog, vec![Binding::key(Key::PageDown)]),
+            (Action::IncreaseExposure, vec![Binding::key(Key::Equals), Binding::key(Key::NumpadAdd)]),
+            (Action::DecreaseExposure, vec![Binding::key(Key::Minus), Binding::key(Key::NumpadSubtract)]),
+        ]);
+
+        Self { bindings }
+    }
+}
+
+impl InputBindings {
+    /// Location of the bindings file, relative to the assets folder.
+    pub const FILE_NAME: &'static str = "config/bindings.ron";
+
+    /// Parses a bindings file. Actions the file lists replace their default
+    /// bindings, actions it leaves out keep them.
+    pub fn from_ron(text: &str) -> anyhow::Result<Self> {
+        let loaded: Self = ron::from_str(text)?;
+        let mut bindings = Self::default();
+        bindings.bindings.extend(loaded.bindings);
+        Ok(bindings)
+    }
+
+    pub async fn load(file_name: &str) -> anyhow::Result<Self> {
+        let text = resources::load_string(file_name).await?;
+        Self::from_ron(&text)
+    }
+}
+
+/// Translates input events into actions using a set of [`InputBindings`].
+pub struct InputMap {
+    bindings: InputBindings,
+    modifiers: Modifiers,
+    /// Actions started by each input that is still held. Releasing the input ends
+    /// exactly these, even if the modifiers changed in the meantime.
+    active: HashMap<Input, Vec<Action>>,
+}
+
+impl InputMap {
+    pub fn new(bindings: InputBindings) -> Self {
+        Self {
+            bindings,
+            modifiers: Modifiers::default(),
+            active: HashMap::new(),
+        }
+    }
+
+    /// Returns the actions started or stopped by `event`.
+    pub fn map_event(&mut self, event: &InputEvent) -> Vec<(Action, ElementState)> {
+        match event {
+            InputEvent::ModifiersChanged(state) => {
+                self.modifiers = Modifiers::from_state(*state);
+                Vec::new()
+            }
+            InputEvent::Keyboard { key, state } => self.map_input(Input::Key(*key), *state),
+            InputEvent::MouseInput { button, state } => self.map_input(Input::Mouse(*button), *state),
+            _ => Vec::new(),
+        }
+    }
+
+    fn map_input(&mut self, input: Input, state: ElementState) -> Vec<(Action, ElementState)> {
+        match state {
+            ElementState::Pressed => {
+                // Ignore key repeat, the actions are already active.
+                if self.active.contains_key(&input) {
+                    return Vec::new();
+                }
+
+                // When several bindings match, only the most specific ones win, so
+                // that e.g. Ctrl+W does not also trigger the action bound to W.
+                let mut matches = Vec::new();
+                let mut best = 0;
+                for (action, bindings) in &self.bindings.bindings {
+                    for binding in bindings {
+                        if binding.input != input || !self.modifiers.contains(&binding.modifiers) {
+                            continue;
+                        }
+                        let count = binding.modifiers.count();
+                        if count > best {
+                            best = count;
+                            matches.clear();
+                        }
+                        if count == best && !matches.contains(action) {
+                            matches.push(*action);
+                        }
+                    }
+                }
+
+                if matches.is_empty() {
+                    return Vec::new();
+                }
+                self.active.insert(input, matches.clone());
+                matches.into_iter().map(|action| (action, state)).collect()
+            }
+            ElementState::Released => self
+                .active
+                .remove(&input)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|action| (action, state))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loaded_bindings_override_defaults() {
+        let bindings = InputBindings::from_ron("(bindings: { MoveForward: [(input: Key(Z))] })").unwrap();
+
+        assert_eq!(bindings.bindings[&Action::MoveForward], vec![Binding::key(VirtualKeyCode::Z)]);
+    }
+
+    #[test]
+    fn actions_missing_from_file_keep_defaults() {
+        let bindings = InputBindings::from_ron("(bindings: { MoveForward: [(input: Key(Z))] })").unwrap();
+        let defaults = InputBindings::default();
+
+        assert_eq!(bindings.bindings.len(), defaults.bindings.len());
+        assert_eq!(bindings.bindings[&Action::Quit], defaults.bindings[&Action::Quit]);
+        assert_eq!(bindings.bindings[&Action::MoveBackward], defaults.bindings[&Action::MoveBackward]);
+    }
+
+    #[test]
+    fn empty_file_gives_defaults() {
+        let bindings = InputBindings::from_ron("(bindings: {})").unwrap();
+
+        assert_eq!(bindings.bindings, InputBindings::default().bindings);
+    }
+}
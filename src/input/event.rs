@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+use winit::event::{
+    DeviceEvent, ElementState, KeyboardInput, ModifiersState, MouseButton, MouseScrollDelta, VirtualKeyCode,
+    WindowEvent,
+};
+
+/// The subset of winit events the application reacts to, in a form that can be
+/// recorded to disk and replayed later.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum InputEvent {
+    Keyboard { key: VirtualKeyCode, state: ElementState },
+    MouseInput { button: MouseButton, state: ElementState },
+    MouseWheel { delta: MouseScrollDelta },
+    MouseMotion { dx: f64, dy: f64 },
+    ModifiersChanged(ModifiersState),
+    Resized { width: u32, height: u32 },
+}
+
+impl InputEvent {
+    pub fn from_window_event(event: &WindowEvent) -> Option<Self> {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(key),
+                        ..
+                    },
+                ..
+            } => Some(Self::Keyboard { key: *key, state: *state }),
+            WindowEvent::MouseInput { button, state, .. } => Some(Self::MouseInput {
+                button: *button,
+                state: *state,
+            }),
+            WindowEvent::MouseWheel { delta, .. } => Some(Self::MouseWheel { delta: *delta }),
+            WindowEvent::ModifiersChanged(state) => Some(Self::ModifiersChanged(*state)),
+            WindowEvent::Resized(size) => Some(Self::Resized {
+                width: size.width,
+                height: size.height,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn from_device_event(event: &DeviceEvent) -> Option<Self> {
+        match event {
+            DeviceEvent::MouseMotion { delta } => Some(Self::MouseMotion { dx: delta.0, dy: delta.1 }),
+            _ => None,
+        }
+    }
+}
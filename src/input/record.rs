@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use instant::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
+use super::event::InputEvent;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimedEvent {
+    /// Seconds since the recording started.
+    pub time: f64,
+    pub event: InputEvent,
+}
+
+/// A recorded input session, stored as RON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recording {
+    /// Window size when the recording started.
+    pub width: u32,
+    pub height: u32,
+    pub events: Vec<TimedEvent>,
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(ron::from_str(&text)?)
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        // One event per line keeps recordings diffable.
+        let config = ron::ser::PrettyConfig::default().depth_limit(2);
+        let text = ron::ser::to_string_pretty(self, config)?;
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+}
+
+/// Timestamps input events as they are handled.
+pub struct Recorder {
+    start: Instant,
+    recording: Recording,
+}
+
+impl Recorder {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            start: Instant::now(),
+            recording: Recording {
+                width,
+                height,
+                events: Vec::new(),
+            },
+        }
+    }
+
+    pub fn record(&mut self, event: InputEvent) {
+        let time = self.start.elapsed().as_secs_f64();
+        self.recording.events.push(TimedEvent { time, event });
+    }
+
+    pub fn finish(self) -> Recording {
+        self.recording
+    }
+}
+
+/// Feeds a recording back frame by frame with a fixed `dt`, so that the same
+/// recording always produces the same sequence of updates.
+pub struct Replay {
+    recording: Recording,
+    next_event: usize,
+    frame: u64,
+    dt: Duration,
+}
+
+impl Replay {
+    pub fn new(recording: Recording, dt: Duration) -> Self {
+        Self {
+            recording,
+            next_event: 0,
+            frame: 0,
+            dt,
+        }
+    }
+
+    pub fn dt(&self) -> Duration {
+        self.dt
+    }
+
+    pub fn size(&self) -> winit::dpi::PhysicalSize<u32> {
+        winit::dpi::PhysicalSize::new(self.recording.width, self.recording.height)
+    }
+
+    /// Returns the events that happened during the next frame, or `None` once the
+    /// whole recording has been played back.
+    pub fn next_frame(&mut self) -> Option<Vec<InputEvent>> {
+        if self.next_event >= self.recording.events.len() {
+            return None;
+        }
+
+        // Frame boundaries are derived from the frame count rather than summed up,
+        // so they don't drift over long recordings.
+        self.frame += 1;
+        let frame_end = self.frame as f64 * self.dt.as_secs_f64();
+
+        let events = &self.recording.events[self.next_event..];
+        let count = events.iter().take_while(|e| e.time < frame_end).count();
+        self.next_event += count;
+
+        Some(events[..count].iter().map(|e| e.event).collect())
+    }
+}
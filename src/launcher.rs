@@ -1,86 +1,295 @@
 // lib.rs
 
+use std::path::PathBuf;
+
+use instant::Duration;
 use winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
     window:: WindowBuilder,
 };
 
-use crate::render::renderer::State;
+use crate::common::{
+    pacing::{FramePacer, Pacing},
+    resources,
+    timestep::FixedTimestep,
+};
+use crate::input::{
+    event::InputEvent,
+    record::{Recorder, Recording, Replay},
+};
+use crate::render::{renderer::State, settings::RenderSettings};
+use crate::scene::SceneSettings;
 
+/// How the application should run.
+#[derive(Debug, Clone)]
+pub struct LaunchOptions {
+    pub scene: SceneSettings,
+    pub render: RenderSettings,
+    /// Folders searched for assets before the default ones, in order.
+    pub assets: Vec<PathBuf>,
+    /// Initial window size in physical pixels, the platform default if `None`.
+    /// Headless frames are [`HEADLESS_SIZE`] by default.
+    pub size: Option<(u32, u32)>,
+    /// Record handled input events to this file when the application exits.
+    pub record: Option<PathBuf>,
+    /// Replay input events from this file instead of reading live input.
+    pub replay: Option<PathBuf>,
+    /// Fixed time step used while replaying.
+    pub replay_dt: Duration,
+    /// Upper limit on frames per second, the loop sleeps between frames to hold it.
+    pub fps_cap: Option<u32>,
+    /// Only redraw when there was input or something is moving.
+    pub low_power: bool,
+    /// Simulation time step, independent from the frame rate.
+    pub timestep: Duration,
+    /// Most simulation steps to run in a single frame before dropping time.
+    pub max_update_steps: u32,
+    /// Exit after rendering this many frames.
+    pub frames: Option<u32>,
+    /// Save the last rendered frame to this file on exit. Requires headless rendering.
+    pub screenshot: Option<PathBuf>,
+}
 
+impl Default for LaunchOptions {
+    fn default() -> Self {
+        Self {
+            scene: SceneSettings::default(),
+            render: RenderSettings::default(),
+            assets: Vec::new(),
+            size: None,
+            record: None,
+            replay: None,
+            replay_dt: Duration::from_secs_f64(1.0 / 60.0),
+            fps_cap: None,
+            low_power: false,
+            timestep: Duration::from_secs_f64(1.0 / 60.0),
+            max_update_steps: 5,
+            frames: None,
+            screenshot: None,
+        }
+    }
+}
 
+/// Size of headless frames when none is given, the usual default window size.
+pub const HEADLESS_SIZE: (u32, u32) = (800, 600);
 
+/// Everything kept around between frames, by the event loop or the headless loop.
+struct App {
+    state: State,
+    options: LaunchOptions,
+    replay: Option<Replay>,
+    recorder: Option<Recorder>,
+    pacer: FramePacer,
+    timestep: FixedTimestep,
+    frames_rendered: u32,
+}
 
-pub async fn run() {
+pub async fn run(options: LaunchOptions) {
     env_logger::init();
+    resources::init_asset_paths(&options.assets);
+
+    let replay = options.replay.as_ref().map(|path| {
+        let recording = Recording::load(path)
+            .unwrap_or_else(|e| panic!("Could not load recording {:?}: {:?}", path, e));
+        Replay::new(recording, options.replay_dt)
+    });
+    if options.render.headless {
+        run_headless(options, replay).await;
+    } else {
+        run_windowed(options, replay).await;
+    }
+}
+
+/// Renders frames back to back in a plain loop until the replay or the frame
+/// count runs out. winit is never touched, so no display is needed.
+async fn run_headless(options: LaunchOptions, replay: Option<Replay>) {
+    if replay.is_none() && options.frames.is_none() {
+        log::warn!("Running headless without a replay or frame count, nothing will stop the application");
+    }
+    let (width, height) = options.size.unwrap_or(HEADLESS_SIZE);
+    let size = winit::dpi::PhysicalSize::new(width, height);
+    let state = State::headless(size, &options.render, &options.scene).await;
+
+    let mut app = App::new(state, options, replay);
+    while app.frame() {}
+    app.finish();
+}
+
+async fn run_windowed(options: LaunchOptions, replay: Option<Replay>) {
     let event_loop = EventLoop::new();
-    let window = WindowBuilder::new()
-        //.with_inner_size(winit::dpi::LogicalSize::new(1003,751)) //comment to get default window size.
-        .build(&event_loop)
-        .unwrap();
+    //.with_inner_size(winit::dpi::LogicalSize::new(1003,751)) //comment to get default window size.
+    let mut window_builder = WindowBuilder::new();
+    if let Some((width, height)) = options.size {
+        window_builder = window_builder.with_inner_size(winit::dpi::PhysicalSize::new(width, height));
+    }
+    let window = window_builder.build(&event_loop).unwrap();
+    let window_id = window.id();
 
-    let mut state = State::new(window).await;
-    let mut last_render_time = instant::Instant::now();  // NEW!
+    let state = State::new(window, &options.render, &options.scene).await;
+    let mut app = App::new(state, options, replay);
 
     event_loop.run(move |event, _, control_flow| match event {
 
-        Event::RedrawRequested(window_id) if window_id == state.window().id() => {
-            let now = instant::Instant::now();
-            let dt = now - last_render_time;
-            last_render_time = now;
-            state.update(dt);
-            match state.render() {
-                Ok(_) => {}
-                // Reconfigure the surface if lost
-                Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
-                // The system is out of memory, we should probably quit
-                Err(wgpu::SurfaceError::OutOfMemory) => *control_flow = ControlFlow::Exit,
-                // All other errors (Outdated, Timeout) should be resolved by the next frame
-                Err(e) => eprintln!("{:?}", e),
+        Event::RedrawRequested(id) if id == window_id => {
+            let running = app.frame();
+            if !running {
+                *control_flow = ControlFlow::Exit;
             }
         }
         Event::MainEventsCleared => {
-            // RedrawRequested will only trigger once, unless we manually
-            // request it.
-            state.window().request_redraw();
+            let animating = app.replay.is_some() || app.state.is_animating();
+            match app.pacer.poll(instant::Instant::now(), animating) {
+                // RedrawRequested will only trigger once, unless we manually
+                // request it.
+                Pacing::Draw => {
+                    if let Some(window) = app.state.window() {
+                        window.request_redraw();
+                    }
+                    control_flow.set_poll();
+                }
+                Pacing::WaitUntil(time) => control_flow.set_wait_until(time),
+                Pacing::Wait => control_flow.set_wait(),
+            }
         }
         Event::WindowEvent {
             ref event,
-            window_id,
-        } if window_id == state.window.id() => {
+            window_id: id,
+        } if id == window_id => {
+
+            let input = InputEvent::from_window_event(event);
+            if let (Some(recorder), Some(input)) = (&mut app.recorder, input) {
+                recorder.record(input);
+            }
 
-            if !state.input(event) {
+            // While replaying, the recording drives the application instead of live input.
+            let handled = app.replay.is_none() && input.is_some_and(|input| app.state.input(&input));
+            if handled {
+                app.pacer.request_redraw();
+                if app.state.quit_requested {
+                    *control_flow = ControlFlow::Exit;
+                }
+            } else {
                 // Si no ha sido manejado, podrías hacer un manejo adicional aquí.
 
                 match event {
                     WindowEvent::Resized(physical_size) => {
-                        state.resize(*physical_size);
+                        app.state.resize(*physical_size);
+                        app.pacer.request_redraw();
                     }
-                    WindowEvent::CloseRequested
-                    | WindowEvent::KeyboardInput {
-                        input:
-                            KeyboardInput {
-                                state: ElementState::Pressed,
-                                virtual_keycode: Some(VirtualKeyCode::Escape),
-                                ..
-                            },
-                        ..
-                    } => *control_flow = ControlFlow::Exit,
+                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
                     WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
-                        state.resize(**new_inner_size);
+                        app.state.resize(**new_inner_size);
+                        app.pacer.request_redraw();
                     }
                     _ => {}
                 }
             }
-            
+
         },
         Event::DeviceEvent {
-            event: DeviceEvent::MouseMotion{ delta, },
+            ref event,
             .. // We're not using device_id currently
-        } => if state.mouse_pressed {
-            state.camera_controller.process_mouse(delta.0, delta.1)
+        } => if let Some(input) = InputEvent::from_device_event(event) {
+            if let Some(recorder) = &mut app.recorder {
+                recorder.record(input);
+            }
+            if app.replay.is_none() && app.state.input(&input) {
+                app.pacer.request_redraw();
+            }
         },
+        Event::LoopDestroyed => app.finish(),
         _ => {}
     });
 }
+
+impl App {
+    fn new(state: State, options: LaunchOptions, replay: Option<Replay>) -> Self {
+        let recorder = options
+            .record
+            .as_ref()
+            .map(|_| Recorder::new(state.size.width, state.size.height));
+        let mut app = App {
+            state,
+            replay,
+            recorder,
+            pacer: FramePacer::new(options.fps_cap, options.low_power),
+            timestep: FixedTimestep::new(options.timestep, options.max_update_steps),
+            frames_rendered: 0,
+            options,
+        };
+
+        // Replays start from the window size they were recorded with.
+        if let Some(size) = app.replay.as_ref().map(Replay::size) {
+            app.resize_to(size);
+        }
+        app
+    }
+
+    /// Runs the fixed updates due since the last frame and renders, feeding in the
+    /// next chunk of the replay if there is one. Returns whether to keep going.
+    fn frame(&mut self) -> bool {
+        let mut dt = self.pacer.frame_dt(instant::Instant::now());
+
+        if let Some(replay) = &mut self.replay {
+            let Some(events) = replay.next_frame() else {
+                return false;
+            };
+            dt = replay.dt();
+            for event in events {
+                match event {
+                    InputEvent::Resized { width, height } => {
+                        self.resize_to(winit::dpi::PhysicalSize::new(width, height))
+                    }
+                    _ => {
+                        self.state.input(&event);
+                    }
+                }
+            }
+            if self.state.quit_requested {
+                return false;
+            }
+        }
+
+        for _ in 0..self.timestep.advance(dt) {
+            self.state.update(self.timestep.step());
+        }
+        match self.state.render(self.timestep.alpha()) {
+            Ok(_) => self.frames_rendered += 1,
+            // Reconfigure the surface if lost
+            Err(wgpu::SurfaceError::Lost) => self.state.resize(self.state.size),
+            // The system is out of memory, we should probably quit
+            Err(wgpu::SurfaceError::OutOfMemory) => return false,
+            // All other errors (Outdated, Timeout) should be resolved by the next frame
+            Err(e) => eprintln!("{:?}", e),
+        }
+
+        self.options.frames.is_none_or(|frames| self.frames_rendered < frames)
+    }
+
+    /// Without a window to report it back, headless rendering is resized directly.
+    /// Otherwise the window is resized and the usual `Resized` event follows.
+    fn resize_to(&mut self, size: winit::dpi::PhysicalSize<u32>) {
+        match self.state.window() {
+            Some(window) => window.set_inner_size(size),
+            None => self.state.resize(size),
+        }
+    }
+
+    /// Writes out whatever was asked to be saved when the application exits.
+    fn finish(&mut self) {
+        if let (Some(recorder), Some(path)) = (self.recorder.take(), &self.options.record) {
+            match recorder.finish().save(path) {
+                Ok(()) => log::info!("Saved input recording to {:?}", path),
+                Err(e) => log::error!("Could not save input recording to {:?}: {:?}", path, e),
+            }
+        }
+
+        if let Some(path) = &self.options.screenshot {
+            match self.state.screenshot().and_then(|image| Ok(image.save(path)?)) {
+                Ok(()) => log::info!("Saved screenshot to {:?}", path),
+                Err(e) => log::error!("Could not save screenshot to {:?}: {:?}", path, e),
+            }
+        }
+    }
+}
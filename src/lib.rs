@@ -1,4 +1,6 @@
 mod render;
 mod scene;
 mod common;
+mod input;
+pub mod cli;
 pub mod launcher;
\ No newline at end of file
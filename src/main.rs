@@ -1,9 +1,9 @@
 
-use rust_graphics::launcher::run;
+use clap::Parser;
+use rust_graphics::{cli::Cli, launcher::run};
 
 
 fn main() {
 
-    pollster::block_on(run());
+    pollster::block_on(run(Cli::parse().into_options()));
 }
-
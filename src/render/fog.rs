@@ -0,0 +1,66 @@
+//! Fog the scene shader fades distant geometry into, and that covers the sky.
+
+use super::{
+    buffer::Buffer,
+    settings::{FogMode, FogSettings},
+};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FogUniform {
+    color: [f32; 4],
+    mode: u32,
+    density: f32,
+    start: f32,
+    end: f32,
+    height: f32,
+    falloff: f32,
+    _padding: [f32; 2],
+}
+
+impl From<&FogSettings> for FogUniform {
+    fn from(settings: &FogSettings) -> Self {
+        // Matches the FOG_* constants in fog.wgsl.
+        let mode = match settings.mode {
+            FogMode::Off => 0,
+            FogMode::Linear => 1,
+            FogMode::Exponential => 2,
+            FogMode::Height => 3,
+        };
+        let [r, g, b] = settings.color;
+        Self {
+            color: [r, g, b, 1.0],
+            mode,
+            density: settings.density,
+            start: settings.start,
+            end: settings.end,
+            height: settings.height,
+            falloff: settings.falloff,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// The fog settings and the uniform buffer the scene and sky shaders read them from.
+pub struct Fog {
+    pub settings: FogSettings,
+    buffer: Buffer<FogUniform>,
+}
+
+impl Fog {
+    pub fn new(device: &wgpu::Device, settings: FogSettings) -> Self {
+        Self {
+            settings,
+            buffer: Buffer::new(device, wgpu::BufferUsages::UNIFORM, &[FogUniform::from(&settings)]),
+        }
+    }
+
+    /// Uploads `settings` after they were changed.
+    pub fn update(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.buffer.buff, 0, bytemuck::cast_slice(&[FogUniform::from(&self.settings)]));
+    }
+
+    pub fn binding(&self) -> wgpu::BindingResource<'_> {
+        self.buffer.buff.as_entire_binding()
+    }
+}
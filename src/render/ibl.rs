@@ -0,0 +1,323 @@
+//! Image-based lighting: the sky prefiltered on the GPU into the maps the scene
+//! shader lights surfaces with.
+
+use std::sync::Arc;
+
+use super::{
+    buffer::Buffer,
+    pipelines::cache::{PipelineCache, PipelineKey},
+    post::{fullscreen_key, fullscreen_pass},
+    texture::Texture,
+};
+
+/// Side of the copy of the sky the maps are filtered from. Its mip levels stand
+/// in for the many samples rough reflections would need otherwise.
+const ENVIRONMENT_SIZE: u32 = 256;
+/// Diffuse lighting changes slowly with the direction, a small map holds it.
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+/// Mip levels of the prefiltered map, going from a roughness of 0 to 1.
+const PREFILTERED_LEVELS: u32 = 5;
+const BRDF_LUT_SIZE: u32 = 256;
+const BRDF_LUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+
+/// Which face, and for prefiltering which roughness, a pass renders.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct IblUniform {
+    face: u32,
+    roughness: f32,
+    _padding: [f32; 2],
+}
+
+/// The lighting a sky gives the scene, in linear HDR units: the irradiance for
+/// diffuse light, the reflections for specular light, blurrier in each mip level,
+/// and the lookup table that scales them by the view angle and roughness.
+pub struct Environment {
+    irradiance: Texture,
+    prefiltered: Texture,
+    brdf_lut: Texture,
+    sampler: wgpu::Sampler,
+}
+
+impl Environment {
+    pub const SHADER: &'static str = "shaders/ibl.wgsl";
+
+    const CUBE_ENTRIES: &'static [wgpu::BindGroupLayoutEntry] = &[
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+    ];
+
+    const UNIFORM_ENTRIES: &'static [wgpu::BindGroupLayoutEntry] = &[wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }];
+
+    fn key(label: &str, fragment: &str) -> PipelineKey {
+        fullscreen_key(label, Self::SHADER, Texture::HDR_FORMAT, None)
+            .entry_points("vs_main", Some(fragment))
+            .bind_group(Self::CUBE_ENTRIES)
+            .bind_group(Self::UNIFORM_ENTRIES)
+    }
+
+    /// Filters `sky`, a cubemap of any size.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cache: &mut PipelineCache,
+        sky: &Texture,
+    ) -> anyhow::Result<Self> {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Encoder"),
+        });
+        let mut generator = Generator::new(device, cache, &mut encoder)?;
+
+        // A copy of the sky with a full mip chain, each level averaging the one
+        // before.
+        let levels = ENVIRONMENT_SIZE.ilog2() + 1;
+        let usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT;
+        let environment =
+            Texture::create_cube(device, ENVIRONMENT_SIZE, levels, Texture::HDR_FORMAT, usage, "environment");
+        for level in 0..levels {
+            let source = match level {
+                0 => generator.cube_bind_group(&sky.view),
+                _ => generator.cube_bind_group(&environment.tex.create_view(&wgpu::TextureViewDescriptor {
+                    dimension: Some(wgpu::TextureViewDimension::Cube),
+                    base_mip_level: level - 1,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })),
+            };
+            let pipeline = Arc::clone(&generator.downsample);
+            generator.render_faces("Environment Downsample", &pipeline, &source, &environment, level, 0.0);
+        }
+
+        let source = generator.cube_bind_group(&environment.view);
+        let irradiance = Texture::create_cube(device, IRRADIANCE_SIZE, 1, Texture::HDR_FORMAT, usage, "irradiance");
+        let pipeline = Arc::clone(&generator.irradiance);
+        generator.render_faces("Irradiance", &pipeline, &source, &irradiance, 0, 0.0);
+
+        let format = Texture::HDR_FORMAT;
+        let prefiltered = Texture::create_cube(device, PREFILTERED_SIZE, PREFILTERED_LEVELS, format, usage, "prefiltered");
+        let pipeline = Arc::clone(&generator.prefilter);
+        for level in 0..PREFILTERED_LEVELS {
+            let roughness = level as f32 / (PREFILTERED_LEVELS - 1) as f32;
+            generator.render_faces("Prefilter", &pipeline, &source, &prefiltered, level, roughness);
+        }
+
+        let brdf_lut = generator.brdf_lut()?;
+        let sampler = generator.sampler;
+        queue.submit(std::iter::once(encoder.finish()));
+
+        Ok(Self {
+            irradiance,
+            prefiltered,
+            brdf_lut,
+            sampler,
+        })
+    }
+
+    /// Lighting from a sky that is the same linear `color` all around, which
+    /// needs no filtering.
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cache: &mut PipelineCache,
+        color: [f32; 3],
+    ) -> anyhow::Result<Self> {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Encoder"),
+        });
+        let mut generator = Generator::new(device, cache, &mut encoder)?;
+
+        let [r, g, b] = color.map(f64::from);
+        let clear = wgpu::LoadOp::Clear(wgpu::Color { r, g, b, a: 1.0 });
+        let usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT;
+        let irradiance = Texture::create_cube(device, 1, 1, Texture::HDR_FORMAT, usage, "irradiance");
+        let prefiltered = Texture::create_cube(device, 1, 1, Texture::HDR_FORMAT, usage, "prefiltered");
+        for cube in [&irradiance, &prefiltered] {
+            for face in 0..6 {
+                // Nothing is drawn, the clear fills the face.
+                generator.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Environment Clear"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &cube.face_view(face, 0),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: clear,
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+            }
+        }
+
+        let brdf_lut = generator.brdf_lut()?;
+        let sampler = generator.sampler;
+        queue.submit(std::iter::once(encoder.finish()));
+
+        Ok(Self {
+            irradiance,
+            prefiltered,
+            brdf_lut,
+            sampler,
+        })
+    }
+
+    pub fn irradiance(&self) -> &wgpu::TextureView {
+        &self.irradiance.view
+    }
+
+    pub fn prefiltered(&self) -> &wgpu::TextureView {
+        &self.prefiltered.view
+    }
+
+    pub fn brdf_lut(&self) -> &wgpu::TextureView {
+        &self.brdf_lut.view
+    }
+
+    /// Filters linearly between texels and mip levels, and clamps the lookup
+    /// table to its edges.
+    pub fn sampler(&self) -> &wgpu::Sampler {
+        &self.sampler
+    }
+}
+
+/// The pipelines and layouts the maps are rendered with, encoding passes until
+/// the maps are done.
+struct Generator<'a> {
+    device: &'a wgpu::Device,
+    cache: &'a mut PipelineCache,
+    encoder: &'a mut wgpu::CommandEncoder,
+    downsample: Arc<wgpu::RenderPipeline>,
+    irradiance: Arc<wgpu::RenderPipeline>,
+    prefilter: Arc<wgpu::RenderPipeline>,
+    cube_layout: Arc<wgpu::BindGroupLayout>,
+    uniform_layout: Arc<wgpu::BindGroupLayout>,
+    sampler: wgpu::Sampler,
+}
+
+impl<'a> Generator<'a> {
+    fn new(
+        device: &'a wgpu::Device,
+        cache: &'a mut PipelineCache,
+        encoder: &'a mut wgpu::CommandEncoder,
+    ) -> anyhow::Result<Self> {
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("environment_sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        Ok(Self {
+            downsample: cache.get(device, &Environment::key("Environment Downsample", "fs_downsample"))?,
+            irradiance: cache.get(device, &Environment::key("Irradiance", "fs_irradiance"))?,
+            prefilter: cache.get(device, &Environment::key("Prefilter", "fs_prefilter"))?,
+            cube_layout: cache.bind_group_layout(device, Environment::CUBE_ENTRIES),
+            uniform_layout: cache.bind_group_layout(device, Environment::UNIFORM_ENTRIES),
+            device,
+            cache,
+            encoder,
+            sampler,
+        })
+    }
+
+    fn cube_bind_group(&self, view: &wgpu::TextureView) -> wgpu::BindGroup {
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.cube_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+            label: Some("environment_cube_bind_group"),
+        })
+    }
+
+    /// Renders all six faces of mip `level` of `target`.
+    fn render_faces(
+        &mut self,
+        label: &str,
+        pipeline: &wgpu::RenderPipeline,
+        source: &wgpu::BindGroup,
+        target: &Texture,
+        level: u32,
+        roughness: f32,
+    ) {
+        for face in 0..6 {
+            let uniform = IblUniform {
+                face,
+                roughness,
+                _padding: [0.0; 2],
+            };
+            let buffer = Buffer::new(self.device, wgpu::BufferUsages::UNIFORM, &[uniform]);
+            let uniform_bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.uniform_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.buff.as_entire_binding(),
+                }],
+                label: Some("ibl_bind_group"),
+            });
+            let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+            let view = target.face_view(face, level);
+            fullscreen_pass(self.encoder, label, pipeline, &[source, &uniform_bind_group], &view, clear);
+        }
+    }
+
+    /// Integrates the lookup table, which only depends on the BRDF.
+    fn brdf_lut(&mut self) -> anyhow::Result<Texture> {
+        let key = fullscreen_key("BRDF LUT", Environment::SHADER, BRDF_LUT_FORMAT, None)
+            .entry_points("vs_main", Some("fs_brdf"));
+        let pipeline = self.cache.get(self.device, &key)?;
+        let tex = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("brdf_lut"),
+            size: wgpu::Extent3d {
+                width: BRDF_LUT_SIZE,
+                height: BRDF_LUT_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: BRDF_LUT_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = tex.create_view(&wgpu::TextureViewDescriptor::default());
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        fullscreen_pass(self.encoder, "BRDF LUT", &pipeline, &[], &view, clear);
+        // Sampled with the environment's sampler.
+        let sampler = self.device.create_sampler(&wgpu::SamplerDescriptor::default());
+        Ok(Texture { tex, view, sampler })
+    }
+}
@@ -1,14 +1,38 @@
 pub mod renderer;
+pub mod fog;
+pub mod ibl;
 pub mod pipelines;
+pub mod post;
 pub mod texture;
 pub mod mesh;
 pub mod model;
 pub mod buffer;
 pub mod model_obj;
+pub mod oit;
+pub mod settings;
+pub mod preprocessor;
+pub mod reflect;
+pub mod shader;
+pub mod ssao;
+pub mod sun;
 
+/// Derives [`Vertex`] from the field types, see the `rust-graphics-derive` crate.
+pub use rust_graphics_derive::Vertex;
 
-pub trait Vertex: Clone + bytemuck::Pod {
-    const STRIDE: wgpu::BufferAddress;
+/// A type stored in vertex buffers. Implement it with `#[derive(Vertex)]` rather
+/// than by hand, so the attributes can't get out of sync with the fields.
+pub trait Vertex: Copy + bytemuck::Pod {
+    const STRIDE: wgpu::BufferAddress = std::mem::size_of::<Self>() as wgpu::BufferAddress;
+    const STEP_MODE: wgpu::VertexStepMode;
+    const ATTRIBUTES: &'static [wgpu::VertexAttribute];
     // Whether these types of verts use the quad index buffer for drawing them
-    const QUADS_INDEX: Option<wgpu::IndexFormat>;
-}
\ No newline at end of file
+    const QUADS_INDEX: Option<wgpu::IndexFormat> = None;
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: Self::STRIDE,
+            step_mode: Self::STEP_MODE,
+            attributes: Self::ATTRIBUTES,
+        }
+    }
+}
@@ -1,61 +1,207 @@
-use super::texture::Texture;
+use anyhow::Context;
+use wgpu::util::DeviceExt;
+
+use super::{texture::Texture, Vertex};
+use crate::common::assets::{Assets, Handle};
 use std::ops::Range;
-pub trait Vertex {
-    fn desc() -> wgpu::VertexBufferLayout<'static>;
-}
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, Vertex)]
 pub struct ModelVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
 }
 
-impl Vertex for ModelVertex {
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
-        use std::mem;
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<ModelVertex>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
-                    shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x2,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
-                    shader_location: 2,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-            ],
+pub struct Model {
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<Handle<Material>>,
+}
+
+/// How a material's alpha is used, like glTF's `alphaMode`. Each mode is drawn
+/// with its own pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum AlphaMode {
+    /// Alpha is ignored.
+    #[default]
+    Opaque,
+    /// Cut out where alpha is below the cutoff, for foliage and fences. With
+    /// MSAA the edges are smoothed with alpha-to-coverage.
+    Mask,
+    /// Blended over what is behind, drawn back to front after everything else.
+    Blend,
+}
+
+/// The constant parts of a material, multiplied with its textures.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialFactors {
+    /// Linear color and alpha.
+    pub base_color: [f32; 4],
+    pub metallic: f32,
+    pub roughness: f32,
+    /// Light the material gives off, in linear HDR units. Above the bloom
+    /// threshold it glows.
+    pub emissive: [f32; 3],
+    /// How far the normal map bends the normals.
+    pub normal_scale: f32,
+    /// How much of the occlusion map is applied, 0 ignores it.
+    pub occlusion_strength: f32,
+    pub alpha_mode: AlphaMode,
+    /// Alpha below which [`AlphaMode::Mask`] materials are cut out.
+    pub alpha_cutoff: f32,
+}
+
+impl Default for MaterialFactors {
+    /// A white, somewhat rough dielectric.
+    fn default() -> Self {
+        Self {
+            base_color: [1.0; 4],
+            metallic: 0.0,
+            roughness: 0.5,
+            emissive: [0.0; 3],
+            normal_scale: 1.0,
+            occlusion_strength: 1.0,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
         }
     }
 }
 
-pub struct Model {
-    pub meshes: Vec<Mesh>,
-    pub materials: Vec<Material>,
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MaterialUniform {
+    base_color: [f32; 4],
+    emissive: [f32; 4],
+    metallic: f32,
+    roughness: f32,
+    normal_scale: f32,
+    occlusion_strength: f32,
+    alpha_cutoff: f32,
+    _padding: [f32; 3],
+}
+
+impl From<&MaterialFactors> for MaterialUniform {
+    fn from(factors: &MaterialFactors) -> Self {
+        let [r, g, b] = factors.emissive;
+        Self {
+            base_color: factors.base_color,
+            emissive: [r, g, b, 0.0],
+            metallic: factors.metallic,
+            roughness: factors.roughness,
+            normal_scale: factors.normal_scale,
+            occlusion_strength: factors.occlusion_strength,
+            alpha_cutoff: factors.alpha_cutoff,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// The textures of a metallic-roughness material, laid out like glTF's. Maps a
+/// material doesn't have point at neutral placeholders.
+#[derive(Debug, Clone)]
+pub struct MaterialTextures {
+    /// sRGB color and linear alpha.
+    pub base_color: Handle<Texture>,
+    /// Roughness in the green channel and metalness in the blue one.
+    pub metallic_roughness: Handle<Texture>,
+    /// Tangent space normals.
+    pub normal: Handle<Texture>,
+    /// Ambient occlusion in the red channel.
+    pub occlusion: Handle<Texture>,
+    /// sRGB color.
+    pub emissive: Handle<Texture>,
+}
+
+impl MaterialTextures {
+    pub fn iter(&self) -> impl Iterator<Item = &Handle<Texture>> {
+        [&self.base_color, &self.metallic_roughness, &self.normal, &self.occlusion, &self.emissive].into_iter()
+    }
 }
 
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: Texture,
+    pub textures: MaterialTextures,
+    pub factors: MaterialFactors,
     pub bind_group: wgpu::BindGroup,
 }
 
+impl Material {
+    /// `assets` has to hold every texture in `textures`, their views and the base
+    /// color's sampler go into the bind group.
+    pub fn new(
+        device: &wgpu::Device,
+        name: String,
+        textures: MaterialTextures,
+        factors: MaterialFactors,
+        assets: &Assets<Texture>,
+        layout: &wgpu::BindGroupLayout,
+    ) -> anyhow::Result<Self> {
+        let get = |handle: &Handle<Texture>| {
+            assets.get(handle).with_context(|| format!("A texture of material {:?} isn't loaded", name))
+        };
+        let base_color = get(&textures.base_color)?;
+        let metallic_roughness = get(&textures.metallic_roughness)?;
+        let normal = get(&textures.normal)?;
+        let occlusion = get(&textures.occlusion)?;
+        let emissive = get(&textures.emissive)?;
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Material Buffer", name)),
+            contents: bytemuck::cast_slice(&[MaterialUniform::from(&factors)]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&base_color.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&base_color.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&metallic_roughness.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&normal.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&occlusion.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&emissive.view),
+                },
+            ],
+            label: Some(&name),
+        });
+
+        Ok(Self {
+            name,
+            textures,
+            factors,
+            bind_group,
+        })
+    }
+}
+
 pub struct Mesh {
     pub name: String,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    /// Middle of the mesh's bounding box, transparent meshes are sorted by it.
+    pub center: [f32; 3],
 }
 
 
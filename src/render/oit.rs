@@ -0,0 +1,232 @@
+//! Weighted blended order-independent transparency, after Morgan McGuire and
+//! Louis Bavoil's "Weighted Blended Order-Independent Transparency". Blended
+//! surfaces are summed up in any order, weighted by their alpha and distance,
+//! so intersecting meshes don't need sorting.
+
+use std::sync::Arc;
+
+use super::{
+    pipelines::cache::{PipelineCache, PipelineKey},
+    post::{fullscreen_key, fullscreen_pass},
+    texture::Texture,
+};
+
+/// Sum of the premultiplied colors and alphas of the surfaces, each times its
+/// weight.
+pub const ACCUM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// Product of one minus the alphas, how much of the scene shows through.
+pub const REVEALAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+/// Adds up the weighted colors and alphas.
+pub const ACCUM_BLEND: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+/// Multiplies the revealage by one minus each alpha.
+pub const REVEALAGE_BLEND: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::OneMinusSrc,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::Zero,
+        dst_factor: wgpu::BlendFactor::OneMinusSrc,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+/// The targets transparent surfaces are accumulated in, and the pass that
+/// composites them over the scene.
+pub struct WeightedBlended {
+    pipeline: Arc<wgpu::RenderPipeline>,
+    layout: Arc<wgpu::BindGroupLayout>,
+    /// Only allocated while enabled, sorted transparency doesn't need them.
+    targets: Option<Targets>,
+}
+
+/// The accumulation and revealage, each with a multisampled texture that is
+/// drawn into and resolved into the other under MSAA, and the bind group the
+/// composite reads them through.
+struct Targets {
+    accum: Texture,
+    revealage: Texture,
+    msaa: Option<[Texture; 2]>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Targets {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> Self {
+        let accum = Texture::create_color_target(device, config, ACCUM_FORMAT, "oit_accum");
+        let revealage = Texture::create_color_target(device, config, REVEALAGE_FORMAT, "oit_revealage");
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&accum.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&revealage.view),
+                },
+            ],
+            label: Some("oit_bind_group"),
+        });
+        Self {
+            accum,
+            revealage,
+            msaa: (sample_count > 1).then(|| {
+                [
+                    Texture::create_msaa_target(device, config, ACCUM_FORMAT, sample_count, "oit_accum_msaa"),
+                    Texture::create_msaa_target(device, config, REVEALAGE_FORMAT, sample_count, "oit_revealage_msaa"),
+                ]
+            }),
+            bind_group,
+        }
+    }
+}
+
+impl WeightedBlended {
+    pub const SHADER: &'static str = "shaders/oit.wgsl";
+
+    /// The accumulation and revealage, read without a sampler.
+    pub const ENTRIES: &'static [wgpu::BindGroupLayoutEntry] = &[
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        },
+    ];
+
+    /// Starts out disabled, without targets.
+    pub fn new(device: &wgpu::Device, cache: &mut PipelineCache) -> anyhow::Result<Self> {
+        Ok(Self {
+            pipeline: cache.get(device, &Self::key())?,
+            layout: cache.bind_group_layout(device, Self::ENTRIES),
+            targets: None,
+        })
+    }
+
+    /// Blends the average color of the surfaces over the scene, by how much
+    /// they cover it.
+    fn key() -> PipelineKey {
+        fullscreen_key("OIT Composite", Self::SHADER, Texture::HDR_FORMAT, Some(wgpu::BlendState::ALPHA_BLENDING))
+            .entry_points("vs_main", Some("fs_composite"))
+            .bind_group(Self::ENTRIES)
+    }
+
+    /// Builds the composite pipeline again, for new shaders.
+    pub fn create_pipelines(&mut self, device: &wgpu::Device, cache: &mut PipelineCache) -> anyhow::Result<()> {
+        self.pipeline = cache.get(device, &Self::key())?;
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.targets.is_some()
+    }
+
+    /// Allocates the targets, or frees them when disabled.
+    pub fn set_enabled(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+        enabled: bool,
+    ) {
+        match (enabled, &self.targets) {
+            (true, None) => self.targets = Some(Targets::new(device, &self.layout, config, sample_count)),
+            (false, Some(_)) => self.targets = None,
+            _ => {}
+        }
+    }
+
+    /// The targets follow the surface size and the scene's sample count.
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, sample_count: u32) {
+        if self.targets.is_some() {
+            self.targets = Some(Targets::new(device, &self.layout, config, sample_count));
+        }
+    }
+
+    fn targets(&self) -> &Targets {
+        self.targets.as_ref().expect("weighted blended transparency is disabled")
+    }
+
+    /// Starts the pass transparent surfaces are accumulated in. They are tested
+    /// against the scene's `depth`, which has to have the same sample count as
+    /// the targets. Only while enabled.
+    pub fn begin_pass<'a>(&'a self, encoder: &'a mut wgpu::CommandEncoder, depth: &'a Texture) -> wgpu::RenderPass<'a> {
+        let targets = self.targets();
+        let attachment = |texture: &'a Texture, msaa: Option<&'a Texture>, clear: wgpu::Color| {
+            let (view, resolve_target, store) = match msaa {
+                Some(msaa) => (&msaa.view, Some(&texture.view), wgpu::StoreOp::Discard),
+                None => (&texture.view, None, wgpu::StoreOp::Store),
+            };
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear),
+                    store,
+                },
+            })
+        };
+        let [accum_msaa, revealage_msaa] = match &targets.msaa {
+            Some([accum, revealage]) => [Some(accum), Some(revealage)],
+            None => [None, None],
+        };
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("OIT Pass"),
+            color_attachments: &[
+                attachment(&targets.accum, accum_msaa, wgpu::Color::TRANSPARENT),
+                // Everything shows through until a surface covers it.
+                attachment(&targets.revealage, revealage_msaa, wgpu::Color::WHITE),
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &depth.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+
+    /// Blends what the pass accumulated over `output`. Only while enabled.
+    pub fn composite(&self, encoder: &mut wgpu::CommandEncoder, output: &wgpu::TextureView) {
+        let bind_groups = [&self.targets().bind_group];
+        fullscreen_pass(encoder, "OIT Composite", &self.pipeline, &bind_groups, output, wgpu::LoadOp::Load);
+    }
+}
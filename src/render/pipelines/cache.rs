@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Context};
+
+use super::super::{reflect, shader::Shader};
+
+/// A vertex buffer layout that owns its attributes, so it can be part of a key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VertexLayout {
+    pub array_stride: wgpu::BufferAddress,
+    pub step_mode: wgpu::VertexStepMode,
+    pub attributes: Vec<wgpu::VertexAttribute>,
+}
+
+impl VertexLayout {
+    fn as_wgpu(&self) -> wgpu::VertexBufferLayout<'_> {
+        wgpu::VertexBufferLayout {
+            array_stride: self.array_stride,
+            step_mode: self.step_mode,
+            attributes: &self.attributes,
+        }
+    }
+}
+
+impl From<wgpu::VertexBufferLayout<'_>> for VertexLayout {
+    fn from(layout: wgpu::VertexBufferLayout<'_>) -> Self {
+        Self {
+            array_stride: layout.array_stride,
+            step_mode: layout.step_mode,
+            attributes: layout.attributes.to_vec(),
+        }
+    }
+}
+
+/// Everything a render pipeline is built from. Built with the methods below,
+/// starting from defaults that suit an opaque pass:
+///
+/// ```ignore
+/// let key = PipelineKey::new("Figure", "shaders/shader.wgsl")
+///     .vertex_layout(ModelVertex::desc())
+///     .bind_group(FigureLayout::ENTRIES)
+///     .color_target(format, Some(wgpu::BlendState::REPLACE))
+///     .depth(Texture::DEPTH_FORMAT, true, wgpu::CompareFunction::Less);
+/// ```
+///
+/// Two equal keys always produce the same pipeline, which is what
+/// [`PipelineCache`] relies on.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub label: String,
+    pub shader: String,
+    /// Preprocessor features the shader is built with.
+    pub features: Vec<String>,
+    pub vertex_entry: String,
+    /// `None` for depth only passes.
+    pub fragment_entry: Option<String>,
+    pub vertex_layouts: Vec<VertexLayout>,
+    /// Layout entries of each bind group, in group order.
+    pub bind_groups: Vec<Vec<wgpu::BindGroupLayoutEntry>>,
+    pub topology: wgpu::PrimitiveTopology,
+    pub front_face: wgpu::FrontFace,
+    pub cull_mode: Option<wgpu::Face>,
+    /// Anything other than `Fill` requires `Features::POLYGON_MODE_LINE`.
+    pub polygon_mode: wgpu::PolygonMode,
+    pub color_targets: Vec<Option<wgpu::ColorTargetState>>,
+    pub depth_stencil: Option<wgpu::DepthStencilState>,
+    pub sample_count: u32,
+    pub alpha_to_coverage: bool,
+}
+
+impl PipelineKey {
+    /// A pipeline drawing filled, back face culled triangle lists with `vs_main`
+    /// and `fs_main` from `shader`, without targets or bind groups.
+    pub fn new(label: &str, shader: &str) -> Self {
+        Self {
+            label: label.to_string(),
+            shader: shader.to_string(),
+            features: Vec::new(),
+            vertex_entry: "vs_main".to_string(),
+            fragment_entry: Some("fs_main".to_string()),
+            vertex_layouts: Vec::new(),
+            bind_groups: Vec::new(),
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode: wgpu::PolygonMode::Fill,
+            color_targets: Vec::new(),
+            depth_stencil: None,
+            sample_count: 1,
+            alpha_to_coverage: false,
+        }
+    }
+
+    pub fn feature(mut self, feature: &str) -> Self {
+        self.features.push(feature.to_string());
+        self
+    }
+
+    pub fn entry_points(mut self, vertex: &str, fragment: Option<&str>) -> Self {
+        self.vertex_entry = vertex.to_string();
+        self.fragment_entry = fragment.map(str::to_string);
+        self
+    }
+
+    pub fn vertex_layout(mut self, layout: wgpu::VertexBufferLayout) -> Self {
+        self.vertex_layouts.push(layout.into());
+        self
+    }
+
+    /// Adds the next bind group.
+    pub fn bind_group(mut self, entries: &[wgpu::BindGroupLayoutEntry]) -> Self {
+        self.bind_groups.push(entries.to_vec());
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: Option<wgpu::Face>) -> Self {
+        self.cull_mode = cull_mode;
+        self
+    }
+
+    pub fn polygon_mode(mut self, polygon_mode: wgpu::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    /// Adds the next color target, writing all channels.
+    pub fn color_target(mut self, format: wgpu::TextureFormat, blend: Option<wgpu::BlendState>) -> Self {
+        self.color_targets.push(Some(wgpu::ColorTargetState {
+            format,
+            blend,
+            write_mask: wgpu::ColorWrites::ALL,
+        }));
+        self
+    }
+
+    pub fn depth(mut self, format: wgpu::TextureFormat, write: bool, compare: wgpu::CompareFunction) -> Self {
+        self.depth_stencil = Some(wgpu::DepthStencilState {
+            format,
+            depth_write_enabled: write,
+            depth_compare: compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        });
+        self
+    }
+
+    pub fn sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    pub fn alpha_to_coverage(mut self, enabled: bool) -> Self {
+        self.alpha_to_coverage = enabled;
+        self
+    }
+}
+
+/// A shader module together with its naga IR, used to check pipelines against it.
+struct CompiledShader {
+    module: wgpu::ShaderModule,
+    ir: naga::Module,
+    info: naga::valid::ModuleInfo,
+    files: Vec<String>,
+}
+
+/// Creates pipelines, bind group layouts and shader modules once per key and
+/// shares them afterwards.
+#[derive(Default)]
+pub struct PipelineCache {
+    layouts: HashMap<Vec<wgpu::BindGroupLayoutEntry>, Arc<wgpu::BindGroupLayout>>,
+    shaders: HashMap<(String, Vec<String>), CompiledShader>,
+    pipelines: HashMap<PipelineKey, Arc<wgpu::RenderPipeline>>,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the bind group layout with these entries. Bind groups and pipelines
+    /// built from the same entries always share the layout.
+    pub fn bind_group_layout(
+        &mut self,
+        device: &wgpu::Device,
+        entries: &[wgpu::BindGroupLayoutEntry],
+    ) -> Arc<wgpu::BindGroupLayout> {
+        self.layouts
+            .entry(entries.to_vec())
+            .or_insert_with(|| {
+                Arc::new(device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: None,
+                    entries,
+                }))
+            })
+            .clone()
+    }
+
+    /// Returns the pipeline for `key`, creating it on first use. Fails if the
+    /// shader doesn't compile or doesn't match the layouts in `key`.
+    pub fn get(&mut self, device: &wgpu::Device, key: &PipelineKey) -> anyhow::Result<Arc<wgpu::RenderPipeline>> {
+        if let Some(pipeline) = self.pipelines.get(key) {
+            return Ok(pipeline.clone());
+        }
+
+        let pipeline = Arc::new(self.create(device, key)?);
+        self.pipelines.insert(key.clone(), pipeline.clone());
+        Ok(pipeline)
+    }
+
+    /// Every shader file the cached pipelines were built from.
+    pub fn shader_files(&self) -> Vec<String> {
+        let mut files = Vec::new();
+        for shader in self.shaders.values() {
+            for file in &shader.files {
+                if !files.contains(file) {
+                    files.push(file.clone());
+                }
+            }
+        }
+        files
+    }
+
+    /// Builds every cached pipeline again from the current shader files. If any of
+    /// them fails, the cache is left as it was and the error returned.
+    pub fn rebuild(&mut self, device: &wgpu::Device) -> anyhow::Result<()> {
+        let mut rebuilt = Self {
+            layouts: self.layouts.clone(),
+            ..Default::default()
+        };
+        for key in self.pipelines.keys() {
+            rebuilt.get(device, key)?;
+        }
+        *self = rebuilt;
+        Ok(())
+    }
+
+    fn shader(&mut self, device: &wgpu::Device, name: &str, features: &[String]) -> anyhow::Result<&CompiledShader> {
+        let id = (name.to_string(), features.to_vec());
+        if !self.shaders.contains_key(&id) {
+            let features = features.iter().map(String::as_str).collect::<Vec<_>>();
+            let shader = Shader::load(name, &features)?;
+            let (ir, info) = shader.parse()?;
+            let module = shader.create_module(device)?;
+            self.shaders.insert(id.clone(), CompiledShader {
+                module,
+                ir,
+                info,
+                files: shader.files().to_vec(),
+            });
+        }
+        Ok(&self.shaders[&id])
+    }
+
+    fn create(&mut self, device: &wgpu::Device, key: &PipelineKey) -> anyhow::Result<wgpu::RenderPipeline> {
+        let bind_group_layouts = key
+            .bind_groups
+            .iter()
+            .map(|entries| self.bind_group_layout(device, entries))
+            .collect::<Vec<_>>();
+        let vertex_layouts = key.vertex_layouts.iter().map(VertexLayout::as_wgpu).collect::<Vec<_>>();
+
+        let shader = self.shader(device, &key.shader, &key.features)?;
+        // Catches layouts that got out of sync with the shader before wgpu does.
+        let bind_groups = key.bind_groups.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let entry_points = std::iter::once(&key.vertex_entry)
+            .chain(&key.fragment_entry)
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        reflect::check_bind_groups(&shader.ir, &shader.info, &entry_points, &bind_groups)
+            .and_then(|()| reflect::check_vertex_buffers(&shader.ir, &key.vertex_entry, &vertex_layouts))
+            .with_context(|| format!("{} doesn't match the {:?} pipeline", key.shader, key.label))?;
+
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format!("{} Pipeline Layout", key.label)),
+            bind_group_layouts: &bind_group_layouts.iter().map(Arc::as_ref).collect::<Vec<_>>(),
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(&format!("{} Pipeline", key.label)),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader.module,
+                entry_point: &key.vertex_entry,
+                buffers: &vertex_layouts,
+            },
+            fragment: key.fragment_entry.as_ref().map(|entry_point| wgpu::FragmentState {
+                module: &shader.module,
+                entry_point,
+                targets: &key.color_targets,
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: key.topology,
+                strip_index_format: None,
+                front_face: key.front_face,
+                cull_mode: key.cull_mode,
+                polygon_mode: key.polygon_mode,
+                // Requires Features::DEPTH_CLIP_CONTROL
+                unclipped_depth: false,
+                // Requires Features::CONSERVATIVE_RASTERIZATION
+                conservative: false,
+            },
+            depth_stencil: key.depth_stencil.clone(),
+            multisample: wgpu::MultisampleState {
+                count: key.sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: key.alpha_to_coverage,
+            },
+            multiview: None,
+        });
+        if let Some(e) = pollster::block_on(device.pop_error_scope()) {
+            bail!("Could not create the {:?} pipeline: {}", key.label, e);
+        }
+
+        log::debug!("Created the {:?} pipeline", key.label);
+        Ok(pipeline)
+    }
+}
@@ -1,44 +1,34 @@
+use std::sync::Arc;
+
 use crate::scene::camera::{self, CameraLayout};
 
 use super::super::{
-    Vertex as VertexTrait,
+    Vertex,
+    oit,
     texture::Texture
 };
 
-use super::super::model_obj::{ModelVertex, Vertex};
+use super::super::model_obj::{AlphaMode, ModelVertex};
+use super::cache::{PipelineCache, PipelineKey};
+use super::lighting::LightingLayout;
 
 /// Represents a Figure Vertex
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable, Vertex)]
+#[vertex(quads_index = Uint16)]
 pub struct FigureVertex {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
 }
 
-impl FigureVertex {
-
-    const ATTRIBS: [wgpu::VertexAttribute; 2] =
-        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x2];
-
-    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
-        wgpu::VertexBufferLayout {
-            array_stride: std::mem::size_of::<Self>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &Self::ATTRIBS,
-
-        }
-    }
-}
-
-impl VertexTrait for FigureVertex {
-    const QUADS_INDEX: Option<wgpu::IndexFormat> = Some(wgpu::IndexFormat::Uint16);
-    const STRIDE: wgpu::BufferAddress = std::mem::size_of::<Self>() as wgpu::BufferAddress;
-}
-
 
 
+// A mat4 takes up 4 vertex slots as it is technically 4 vec4s, which have to be
+// reassembled in the shader. Locations 0 to 4 are left for the vertex, and the
+// shader moves on to the next instance when it starts processing a new one.
 #[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Vertex)]
+#[vertex(location = 5, step_mode = Instance)]
 pub struct Instance {
     pub model: [[f32; 4]; 4],
 }
@@ -53,154 +43,213 @@ impl Instance {
             model: (cgmath::Matrix4::from_translation(position) * cgmath::Matrix4::from(rotation)).into()
         }
     }
-
-    fn desc() -> wgpu::VertexBufferLayout<'static> {
-        use std::mem;
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<Instance>() as wgpu::BufferAddress,
-            // We need to switch from using a step mode of Vertex to Instance
-            // This means that our shaders will only change to use the next
-            // instance when the shader starts processing a new instance
-            step_mode: wgpu::VertexStepMode::Instance,
-            attributes: &[
-                // A mat4 takes up 4 vertex slots as it is technically 4 vec4s. We need to define a slot
-                // for each vec4. We'll have to reassemble the mat4 in the shader.
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    // While our vertex shader only uses locations 0, and 1 now, in later tutorials, we'll
-                    // be using 2, 3, and 4, for Vertex. We'll start at slot 5, not conflict with them later
-                    shader_location: 5,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
-                    shader_location: 6,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
-                    shader_location: 7,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
-                    shader_location: 8,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-            ],
-        }
-    }
 }
  
 
 
 pub struct FigureLayout {
-    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group_layout: Arc<wgpu::BindGroupLayout>,
 }
 
 
+/// A filterable 2D texture read by the fragment shader.
+const fn material_texture(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    }
+}
+
 impl FigureLayout {
-    pub fn new(device: &wgpu::Device) -> Self {
+    /// The material: base color texture, the sampler for all textures, the
+    /// factors, then the metallic-roughness, normal, occlusion and emissive maps.
+    pub const ENTRIES: &'static [wgpu::BindGroupLayoutEntry] = &[
+        material_texture(0),
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            // This should match the filterable field of the
+            // corresponding Texture entry above.
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+        material_texture(3),
+        material_texture(4),
+        material_texture(5),
+        material_texture(6),
+    ];
+
+    pub fn new(device: &wgpu::Device, cache: &mut PipelineCache) -> Self {
         Self {
-            bind_group_layout: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        // This should match the filterable field of the
-                        // corresponding Texture entry above.
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("figure_bind_group_layout"),
-            }),
+            bind_group_layout: cache.bind_group_layout(device, Self::ENTRIES),
         }
     }
 }
 
 pub struct FigurePipeline {
-    pub pipeline: wgpu::RenderPipeline,
+    pub pipeline: Arc<wgpu::RenderPipeline>,
 }
 
 impl FigurePipeline {
+    pub const SHADER: &'static str = "shaders/shader.wgsl";
+
+    /// Textured models with depth testing, for materials of `alpha_mode`.
+    /// Wireframes are shaded like filled models but ignore alpha.
+    ///
+    /// Depth passes with `LessEqual`, so the same geometry passes again after the
+    /// depth prepass drew it. Blended materials don't write depth, so they don't
+    /// hide each other, and are drawn after everything else.
+    pub fn key(
+        format: wgpu::TextureFormat,
+        polygon_mode: wgpu::PolygonMode,
+        alpha_mode: AlphaMode,
+        sample_count: u32,
+    ) -> PipelineKey {
+        let key = PipelineKey::new("Figure", Self::SHADER)
+            .vertex_layout(ModelVertex::desc()) //Instance::desc()//
+            .bind_group(FigureLayout::ENTRIES)
+            .bind_group(CameraLayout::ENTRIES) //temporary until i add a way to reference global layouts
+            .bind_group(LightingLayout::ENTRIES)
+            .polygon_mode(polygon_mode)
+            .sample_count(sample_count);
+        if polygon_mode != wgpu::PolygonMode::Fill {
+            return key
+                .color_target(format, Some(wgpu::BlendState::REPLACE))
+                .depth(Texture::DEPTH_FORMAT, true, wgpu::CompareFunction::LessEqual);
+        }
+        match alpha_mode {
+            AlphaMode::Opaque => key
+                .color_target(format, Some(wgpu::BlendState::REPLACE))
+                .depth(Texture::DEPTH_FORMAT, true, wgpu::CompareFunction::LessEqual),
+            AlphaMode::Mask => Self::alpha_test(key, sample_count)
+                .color_target(format, Some(wgpu::BlendState::REPLACE))
+                .depth(Texture::DEPTH_FORMAT, true, wgpu::CompareFunction::LessEqual),
+            AlphaMode::Blend => key
+                .feature("ALPHA_BLEND")
+                .color_target(format, Some(wgpu::BlendState::ALPHA_BLENDING))
+                .depth(Texture::DEPTH_FORMAT, false, wgpu::CompareFunction::LessEqual),
+        }
+    }
+
+    /// Cuts out fragments below the material's alpha cutoff. Under MSAA the
+    /// alpha turns into sample coverage instead, which smooths the edges.
+    fn alpha_test(key: PipelineKey, sample_count: u32) -> PipelineKey {
+        let key = key.feature("ALPHA_MASK");
+        if sample_count > 1 {
+            key.feature("ALPHA_TO_COVERAGE").alpha_to_coverage(true)
+        } else {
+            key
+        }
+    }
+
+    /// Only writes depth, for the passes that need the scene's depth before it
+    /// is shaded. Opaque materials need no fragment shader, alpha tested ones
+    /// run `fs_depth_mask` to leave their cut out parts empty. Blended materials
+    /// aren't part of the scene's depth.
+    pub fn depth_key(alpha_mode: AlphaMode, sample_count: u32) -> PipelineKey {
+        let key = PipelineKey::new("Figure Depth", Self::SHADER)
+            .vertex_layout(ModelVertex::desc())
+            .bind_group(FigureLayout::ENTRIES)
+            .bind_group(CameraLayout::ENTRIES)
+            .depth(Texture::DEPTH_FORMAT, true, wgpu::CompareFunction::Less)
+            .sample_count(sample_count);
+        match alpha_mode {
+            AlphaMode::Mask => key.feature("ALPHA_MASK").entry_points("vs_main", Some("fs_depth_mask")),
+            AlphaMode::Opaque | AlphaMode::Blend => key.entry_points("vs_main", None),
+        }
+    }
+
+    /// Blended materials accumulated for order-independent transparency, see
+    /// [`oit::WeightedBlended`]. Like the sorted ones they don't write depth.
+    pub fn oit_key(sample_count: u32) -> PipelineKey {
+        PipelineKey::new("Figure OIT", Self::SHADER)
+            .feature("ALPHA_BLEND")
+            .entry_points("vs_main", Some("fs_oit"))
+            .vertex_layout(ModelVertex::desc())
+            .bind_group(FigureLayout::ENTRIES)
+            .bind_group(CameraLayout::ENTRIES)
+            .bind_group(LightingLayout::ENTRIES)
+            .color_target(oit::ACCUM_FORMAT, Some(oit::ACCUM_BLEND))
+            .color_target(oit::REVEALAGE_FORMAT, Some(oit::REVEALAGE_BLEND))
+            .depth(Texture::DEPTH_FORMAT, false, wgpu::CompareFunction::LessEqual)
+            .sample_count(sample_count)
+    }
+
+    pub fn new(device: &wgpu::Device, cache: &mut PipelineCache, key: &PipelineKey) -> anyhow::Result<Self> {
+        Ok(Self {
+            pipeline: cache.get(device, key)?,
+        })
+    }
+}
+
+/// Every pipeline models are drawn with, rebuilt together when the shaders or
+/// the sample count change.
+pub struct FigurePipelines {
+    pub opaque: FigurePipeline,
+    pub mask: FigurePipeline,
+    pub blend: FigurePipeline,
+    /// Blended materials with order-independent transparency instead of sorting.
+    pub oit: FigurePipeline,
+    /// Only available when the adapter supports `POLYGON_MODE_LINE`.
+    pub wireframe: Option<FigurePipeline>,
+    /// Fill the depth texture before the scene is shaded, for SSAO.
+    pub depth: FigurePipeline,
+    pub depth_mask: FigurePipeline,
+}
+
+impl FigurePipelines {
     pub fn new(
         device: &wgpu::Device,
-        shader: &wgpu::ShaderModule,
-        config: &wgpu::SurfaceConfiguration,
-        layout: &FigureLayout,
-        camera_layout: &CameraLayout //temporary until i add a way to reference global layouts
-    ) -> Self {
-
-        let pipeline_layout =
-        device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Render Figure Pipeline Layout"),
-            bind_group_layouts: &[
-                &layout.bind_group_layout,
-                &camera_layout.bind_group_layout
-            ],
-            push_constant_ranges: &[],
-        });
-
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Figure Pipeline"),
-            layout: Some(&pipeline_layout),
-            primitive: wgpu::PrimitiveState { 
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                // Setting this to anything other than Fill requires Features::NON_FILL_POLYGON_MODE
-                polygon_mode: wgpu::PolygonMode::Fill,
-                // Requires Features::DEPTH_CLIP_CONTROL
-                unclipped_depth: false,
-                // Requires Features::CONSERVATIVE_RASTERIZATION
-                conservative: false,
-            },
-            vertex: wgpu::VertexState {
-                module: shader,
-                entry_point: "vs_main",
-                buffers: &[
-                    ModelVertex::desc(), //Instance::desc()//
-                ],
-            },
-            fragment: Some(wgpu::FragmentState {
-                module: shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-            }),
-            depth_stencil: Some(wgpu::DepthStencilState {
-                format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less, // 1.
-                stencil: wgpu::StencilState::default(), // 2.
-                bias: wgpu::DepthBiasState::default(),
-            }),
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false,
-            },
-            multiview: None,
-        });
+        cache: &mut PipelineCache,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> anyhow::Result<Self> {
+        let mut filled = |alpha_mode| {
+            let key = FigurePipeline::key(format, wgpu::PolygonMode::Fill, alpha_mode, sample_count);
+            FigurePipeline::new(device, cache, &key)
+        };
+        let opaque = filled(AlphaMode::Opaque)?;
+        let mask = filled(AlphaMode::Mask)?;
+        let blend = filled(AlphaMode::Blend)?;
+        let wireframe = if device.features().contains(wgpu::Features::POLYGON_MODE_LINE) {
+            let key = FigurePipeline::key(format, wgpu::PolygonMode::Line, AlphaMode::Opaque, sample_count);
+            Some(FigurePipeline::new(device, cache, &key)?)
+        } else {
+            None
+        };
+        Ok(Self {
+            opaque,
+            mask,
+            blend,
+            oit: FigurePipeline::new(device, cache, &FigurePipeline::oit_key(sample_count))?,
+            wireframe,
+            depth: FigurePipeline::new(device, cache, &FigurePipeline::depth_key(AlphaMode::Opaque, sample_count))?,
+            depth_mask: FigurePipeline::new(device, cache, &FigurePipeline::depth_key(AlphaMode::Mask, sample_count))?,
+        })
+    }
 
-        
-        Self {
-            pipeline,
+    /// The pipeline for filled meshes with materials of `alpha_mode`.
+    pub fn filled(&self, alpha_mode: AlphaMode) -> &FigurePipeline {
+        match alpha_mode {
+            AlphaMode::Opaque => &self.opaque,
+            AlphaMode::Mask => &self.mask,
+            AlphaMode::Blend => &self.blend,
         }
     }
-}
\ No newline at end of file
+}
@@ -1 +1,4 @@
-pub mod figure;
\ No newline at end of file
+pub mod cache;
+pub mod figure;
+pub mod lighting;
+pub mod skybox;
\ No newline at end of file
@@ -0,0 +1,235 @@
+use std::sync::Arc;
+
+use wgpu::util::DeviceExt;
+
+use crate::scene::camera::CameraLayout;
+
+use super::super::buffer::Buffer;
+use super::super::fog::Fog;
+use super::super::post::{fullscreen_key, fullscreen_pass};
+use super::super::texture::Texture;
+use super::cache::{PipelineCache, PipelineKey};
+
+const EQUIRECTANGULAR_SHADER: &str = "shaders/equirectangular.wgsl";
+
+/// The panorama, 32 bit float and so read without a sampler.
+const EQUIRECTANGULAR_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[wgpu::BindGroupLayoutEntry {
+    binding: 0,
+    visibility: wgpu::ShaderStages::FRAGMENT,
+    ty: wgpu::BindingType::Texture {
+        multisampled: false,
+        view_dimension: wgpu::TextureViewDimension::D2,
+        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+    },
+    count: None,
+}];
+
+/// The index of the cube face being rendered.
+const FACE_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[wgpu::BindGroupLayoutEntry {
+    binding: 0,
+    visibility: wgpu::ShaderStages::FRAGMENT,
+    ty: wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Uniform,
+        has_dynamic_offset: false,
+        min_binding_size: None,
+    },
+    count: None,
+}];
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// The sky cubemap drawn behind the scene, after everything opaque.
+pub struct Skybox {
+    pub pipeline: Arc<wgpu::RenderPipeline>,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl Skybox {
+    pub const SHADER: &'static str = "shaders/sky.wgsl";
+
+    pub const ENTRIES: &'static [wgpu::BindGroupLayoutEntry] = &[
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::Cube,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+        // The fog the sky fades into, see fog.wgsl.
+        wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+    ];
+
+    /// Drawn at the far plane, so it passes the depth test only where the depth
+    /// texture is still clear, and doesn't write depth itself.
+    pub fn key(format: wgpu::TextureFormat, sample_count: u32) -> PipelineKey {
+        PipelineKey::new("Skybox", Self::SHADER)
+            .bind_group(Self::ENTRIES)
+            .bind_group(CameraLayout::ENTRIES)
+            .cull_mode(None)
+            .color_target(format, None)
+            .depth(Texture::DEPTH_FORMAT, false, wgpu::CompareFunction::LessEqual)
+            .sample_count(sample_count)
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        texture: &Texture,
+        fog: &Fog,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> anyhow::Result<Self> {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &cache.bind_group_layout(device, Self::ENTRIES),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: fog.binding(),
+                },
+            ],
+            label: Some("skybox_bind_group"),
+        });
+        Ok(Self {
+            pipeline: cache.get(device, &Self::key(format, sample_count))?,
+            bind_group,
+        })
+    }
+
+    /// Converts an equirectangular panorama into an HDR cubemap on the GPU. Images
+    /// that aren't floating point are taken to be sRGB.
+    pub fn cube_from_equirectangular(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cache: &mut PipelineCache,
+        image: &image::DynamicImage,
+        label: &str,
+    ) -> anyhow::Result<Texture> {
+        let (width, height) = (image.width(), image.height());
+        let max_size = device.limits().max_texture_dimension_2d;
+        if width > max_size || height > max_size {
+            anyhow::bail!("{}x{} is larger than the biggest supported texture, {}", width, height, max_size);
+        }
+
+        let is_float = matches!(image, image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_));
+        let mut pixels = image.to_rgba32f();
+        if !is_float {
+            for pixel in pixels.pixels_mut() {
+                for channel in &mut pixel.0[..3] {
+                    *channel = srgb_to_linear(*channel);
+                }
+            }
+        }
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let source = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some(&format!("{}_equirectangular", label)),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            bytemuck::cast_slice(pixels.as_raw()),
+        );
+
+        // A face a quarter of the panorama wide keeps about the same detail.
+        let face_size = (width / 4).clamp(1, 2048);
+        let usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT;
+        let cube = Texture::create_cube(device, face_size, 1, Texture::HDR_FORMAT, usage, label);
+
+        let key = fullscreen_key("Equirectangular to Cube", EQUIRECTANGULAR_SHADER, Texture::HDR_FORMAT, None)
+            .bind_group(EQUIRECTANGULAR_ENTRIES)
+            .bind_group(FACE_ENTRIES);
+        let pipeline = cache.get(device, &key)?;
+        let source_view = source.create_view(&wgpu::TextureViewDescriptor::default());
+        let source_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &cache.bind_group_layout(device, EQUIRECTANGULAR_ENTRIES),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&source_view),
+            }],
+            label: Some("equirectangular_bind_group"),
+        });
+        let face_layout = cache.bind_group_layout(device, FACE_ENTRIES);
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Equirectangular Encoder"),
+        });
+        for face in 0..6u32 {
+            let face_buffer = Buffer::new(device, wgpu::BufferUsages::UNIFORM, &[[face, 0, 0, 0]]);
+            let face_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &face_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: face_buffer.buff.as_entire_binding(),
+                }],
+                label: Some("cube_face_bind_group"),
+            });
+            let face_view = cube.face_view(face, 0);
+            let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+            let bind_groups = [&source_bind_group, &face_bind_group];
+            fullscreen_pass(&mut encoder, "Equirectangular to Cube", &pipeline, &bind_groups, &face_view, clear);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+
+        Ok(cube)
+    }
+
+    /// Builds the pipeline again, for new shaders or another sample count.
+    pub fn create_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> anyhow::Result<()> {
+        self.pipeline = cache.get(device, &Self::key(format, sample_count))?;
+        Ok(())
+    }
+
+    /// Draws the sky into a pass that has the camera bound to group 1.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,217 @@
+use std::sync::Arc;
+
+use super::super::{
+    buffer::Buffer,
+    pipelines::cache::{PipelineCache, PipelineKey},
+    settings::BloomSettings,
+    texture::Texture,
+};
+
+use super::{fullscreen_key, fullscreen_pass, input_bind_group, PostContext, PostEffect, Stage, INPUT_ENTRIES};
+
+/// Mips below this many pixels on their short side aren't worth blurring into.
+const MIN_MIP_SIZE: u32 = 8;
+const MAX_MIPS: u32 = 6;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    radius: f32,
+}
+
+impl From<&BloomSettings> for BloomUniform {
+    fn from(settings: &BloomSettings) -> Self {
+        Self {
+            threshold: settings.threshold,
+            knee: settings.knee,
+            intensity: settings.intensity,
+            radius: settings.radius,
+        }
+    }
+}
+
+struct BloomPipelines {
+    prefilter: Arc<wgpu::RenderPipeline>,
+    downsample: Arc<wgpu::RenderPipeline>,
+    upsample: Arc<wgpu::RenderPipeline>,
+    composite: Arc<wgpu::RenderPipeline>,
+}
+
+/// Makes everything brighter than a threshold glow.
+///
+/// The bright parts are downsampled through a mip chain starting at half
+/// resolution, then upsampled back up with each mip added onto the next larger
+/// one, and the result added to the scene. Runs on the HDR scene, so only what
+/// is actually brighter than white blooms.
+pub struct Bloom {
+    pipelines: BloomPipelines,
+    input_layout: Arc<wgpu::BindGroupLayout>,
+    /// Kept alive for the bind group.
+    _buffer: Buffer<BloomUniform>,
+    bind_group: wgpu::BindGroup,
+    /// The mip chain, one view per level.
+    mips: Vec<wgpu::TextureView>,
+    sampler: wgpu::Sampler,
+}
+
+impl Bloom {
+    pub const NAME: &'static str = "Bloom";
+    pub const SHADER: &'static str = "shaders/bloom.wgsl";
+
+    pub const ENTRIES: &'static [wgpu::BindGroupLayoutEntry] = &[
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+    ];
+
+    pub fn new(
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        config: &wgpu::SurfaceConfiguration,
+        settings: BloomSettings,
+    ) -> anyhow::Result<Self> {
+        let buffer = Buffer::new(device, wgpu::BufferUsages::UNIFORM, &[BloomUniform::from(&settings)]);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &cache.bind_group_layout(device, Self::ENTRIES),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.buff.as_entire_binding(),
+            }],
+            label: Some("bloom_bind_group"),
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("bloom_sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Ok(Self {
+            pipelines: Self::pipelines(device, cache, Texture::HDR_FORMAT)?,
+            input_layout: cache.bind_group_layout(device, INPUT_ENTRIES),
+            _buffer: buffer,
+            bind_group,
+            mips: Self::create_mips(device, config),
+            sampler,
+        })
+    }
+
+    fn pipelines(
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        format: wgpu::TextureFormat,
+    ) -> anyhow::Result<BloomPipelines> {
+        let key = |fragment_entry: &str, blend: Option<wgpu::BlendState>| -> PipelineKey {
+            fullscreen_key(&format!("Bloom {}", fragment_entry), Self::SHADER, format, blend)
+                .entry_points("vs_main", Some(fragment_entry))
+                .bind_group(INPUT_ENTRIES)
+                .bind_group(Self::ENTRIES)
+        };
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let additive = Some(wgpu::BlendState { color: additive, alpha: additive });
+
+        Ok(BloomPipelines {
+            prefilter: cache.get(device, &key("fs_prefilter", None))?,
+            downsample: cache.get(device, &key("fs_downsample", None))?,
+            upsample: cache.get(device, &key("fs_upsample", additive))?,
+            composite: cache.get(device, &key("fs_composite", None).bind_group(INPUT_ENTRIES))?,
+        })
+    }
+
+    /// A texture at half the surface size with as many mips as fit, down to
+    /// [`MIN_MIP_SIZE`].
+    fn create_mips(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Vec<wgpu::TextureView> {
+        let width = (config.width / 2).max(1);
+        let height = (config.height / 2).max(1);
+        let short_side = width.min(height);
+        let mip_level_count = (short_side / MIN_MIP_SIZE).max(1).ilog2().clamp(1, MAX_MIPS);
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("bloom_mips"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Texture::HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        (0..mip_level_count)
+            .map(|mip| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some(&format!("bloom_mip_{}", mip)),
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect()
+    }
+}
+
+impl PostEffect for Bloom {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::Hdr
+    }
+
+    fn create_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        format: wgpu::TextureFormat,
+    ) -> anyhow::Result<()> {
+        self.pipelines = Self::pipelines(device, cache, format)?;
+        Ok(())
+    }
+
+    fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.mips = Self::create_mips(device, config);
+    }
+
+    fn render(&mut self, ctx: &mut PostContext, input: &Texture, output: &wgpu::TextureView) {
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        let bind = |view: &wgpu::TextureView| input_bind_group(ctx.device, &self.input_layout, view, &self.sampler);
+
+        let pipelines = &self.pipelines;
+        let scene = bind(&input.view);
+        let bind_groups = [&scene, &self.bind_group];
+        fullscreen_pass(ctx.encoder, "Bloom Prefilter", &pipelines.prefilter, &bind_groups, &self.mips[0], clear);
+
+        for pair in self.mips.windows(2) {
+            let source = bind(&pair[0]);
+            let bind_groups = [&source, &self.bind_group];
+            fullscreen_pass(ctx.encoder, "Bloom Downsample", &pipelines.downsample, &bind_groups, &pair[1], clear);
+        }
+        for pair in self.mips.windows(2).rev() {
+            let source = bind(&pair[1]);
+            let bind_groups = [&source, &self.bind_group];
+            fullscreen_pass(ctx.encoder, "Bloom Upsample", &pipelines.upsample, &bind_groups, &pair[0], wgpu::LoadOp::Load);
+        }
+
+        let glow = bind(&self.mips[0]);
+        let bind_groups = [&scene, &self.bind_group, &glow];
+        fullscreen_pass(ctx.encoder, "Bloom Composite", &pipelines.composite, &bind_groups, output, clear);
+    }
+}
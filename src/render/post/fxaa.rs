@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use super::super::{
+    pipelines::cache::{PipelineCache, PipelineKey},
+    texture::Texture,
+};
+
+use super::{fullscreen_key, fullscreen_pass, input_bind_group, PostContext, PostEffect, Stage, INPUT_ENTRIES};
+
+/// Fast approximate anti-aliasing. Smooths every edge in the image, including
+/// the ones MSAA can't see, like those inside textures or from other effects.
+pub struct Fxaa {
+    pipeline: Arc<wgpu::RenderPipeline>,
+    input_layout: Arc<wgpu::BindGroupLayout>,
+}
+
+impl Fxaa {
+    pub const NAME: &'static str = "FXAA";
+    pub const SHADER: &'static str = "shaders/fxaa.wgsl";
+
+    /// sRGB targets are sampled as linear colors, which the shader converts back
+    /// to find edges the way they are seen.
+    pub fn key(format: wgpu::TextureFormat) -> PipelineKey {
+        let key = fullscreen_key("FXAA", Self::SHADER, format, None).bind_group(INPUT_ENTRIES);
+        if format.is_srgb() {
+            key.feature("LINEAR_INPUT")
+        } else {
+            key
+        }
+    }
+
+    pub fn new(device: &wgpu::Device, cache: &mut PipelineCache, format: wgpu::TextureFormat) -> anyhow::Result<Self> {
+        Ok(Self {
+            pipeline: cache.get(device, &Self::key(format))?,
+            input_layout: cache.bind_group_layout(device, INPUT_ENTRIES),
+        })
+    }
+}
+
+impl PostEffect for Fxaa {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn stage(&self) -> Stage {
+        Stage::Ldr
+    }
+
+    fn create_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        format: wgpu::TextureFormat,
+    ) -> anyhow::Result<()> {
+        self.pipeline = cache.get(device, &Self::key(format))?;
+        Ok(())
+    }
+
+    fn render(&mut self, ctx: &mut PostContext, input: &Texture, output: &wgpu::TextureView) {
+        let input = input_bind_group(ctx.device, &self.input_layout, &input.view, &input.sampler);
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        fullscreen_pass(ctx.encoder, "FXAA Pass", &self.pipeline, &[&input], output, clear);
+    }
+}
@@ -0,0 +1,270 @@
+//! Fullscreen effects applied to the rendered scene.
+//!
+//! The scene is drawn into an HDR target. Effects of the [`Stage::Hdr`] stage
+//! run on it first, then it is tonemapped, and the [`Stage::Ldr`] effects run on
+//! the tonemapped image. Each effect reads the previous effect's output and
+//! writes into the other target of a ping-pong pair. The last pass writes
+//! straight into the frame.
+
+pub mod bloom;
+pub mod fxaa;
+pub mod tonemap;
+
+use super::{
+    pipelines::cache::{PipelineCache, PipelineKey},
+    texture::Texture,
+};
+
+use tonemap::Tonemap;
+
+/// Where in the chain an effect runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// On the linear HDR scene, before tonemapping.
+    Hdr,
+    /// On the tonemapped image, in the surface format.
+    Ldr,
+}
+
+/// What effects get to record their passes with.
+pub struct PostContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub encoder: &'a mut wgpu::CommandEncoder,
+}
+
+/// A fullscreen effect in the post-processing chain.
+///
+/// Most effects are a single pass: a pipeline built from a [`fullscreen_key`]
+/// and a bind group with the input, drawn with [`fullscreen_pass`].
+pub trait PostEffect {
+    /// Shown in logs and used to toggle the effect.
+    fn name(&self) -> &'static str;
+
+    fn stage(&self) -> Stage;
+
+    /// Builds the pipelines again, e.g. after the shaders were reloaded.
+    /// `format` is the format of the targets in the effect's stage.
+    fn create_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        format: wgpu::TextureFormat,
+    ) -> anyhow::Result<()>;
+
+    /// Called when the window changes size. Effects with targets of their own
+    /// recreate them here.
+    fn resize(&mut self, _device: &wgpu::Device, _config: &wgpu::SurfaceConfiguration) {}
+
+    /// Records the passes reading `input` and writing all of `output`.
+    fn render(&mut self, ctx: &mut PostContext, input: &Texture, output: &wgpu::TextureView);
+}
+
+struct Entry {
+    effect: Box<dyn PostEffect>,
+    enabled: bool,
+}
+
+/// The ordered post-processing effects and the targets they run on.
+pub struct PostProcessing {
+    effects: Vec<Entry>,
+    pub tonemap: Tonemap,
+    /// The scene is drawn into the first one.
+    hdr_targets: [Texture; 2],
+    ldr_targets: [Texture; 2],
+    format: wgpu::TextureFormat,
+}
+
+impl PostProcessing {
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, tonemap: Tonemap) -> Self {
+        Self {
+            effects: Vec::new(),
+            tonemap,
+            hdr_targets: Self::create_targets(device, config, Texture::HDR_FORMAT, "hdr_target"),
+            ldr_targets: Self::create_targets(device, config, config.format, "ldr_target"),
+            format: config.format,
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> [Texture; 2] {
+        [0, 1].map(|i| Texture::create_color_target(device, config, format, &format!("{}_{}", label, i)))
+    }
+
+    /// The HDR texture the scene has to be rendered into.
+    pub fn scene_target(&self) -> &Texture {
+        &self.hdr_targets[0]
+    }
+
+    /// Appends an effect, enabled, after the others of its stage.
+    pub fn add(&mut self, effect: impl PostEffect + 'static) {
+        self.effects.push(Entry {
+            effect: Box::new(effect),
+            enabled: true,
+        });
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.effects.iter().any(|entry| entry.effect.name() == name && entry.enabled)
+    }
+
+    /// Turns the effect called `name` on or off. Returns false if there is none.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.effects.iter_mut().find(|entry| entry.effect.name() == name) {
+            Some(entry) => {
+                entry.enabled = enabled;
+                log::info!("{} {}", name, if enabled { "enabled" } else { "disabled" });
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn toggle(&mut self, name: &str) -> bool {
+        let enabled = !self.is_enabled(name);
+        self.set_enabled(name, enabled)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.hdr_targets = Self::create_targets(device, config, Texture::HDR_FORMAT, "hdr_target");
+        self.ldr_targets = Self::create_targets(device, config, config.format, "ldr_target");
+        for entry in &mut self.effects {
+            entry.effect.resize(device, config);
+        }
+    }
+
+    /// Builds the pipelines of the tonemapper and every effect again.
+    pub fn create_pipelines(&mut self, device: &wgpu::Device, cache: &mut PipelineCache) -> anyhow::Result<()> {
+        self.tonemap.create_pipelines(device, cache, self.format)?;
+        for entry in &mut self.effects {
+            let format = match entry.effect.stage() {
+                Stage::Hdr => Texture::HDR_FORMAT,
+                Stage::Ldr => self.format,
+            };
+            entry.effect.create_pipelines(device, cache, format)?;
+        }
+        Ok(())
+    }
+
+    /// Runs the enabled effects and the tonemapper on the scene target, ending in
+    /// `frame`.
+    pub fn render(&mut self, ctx: &mut PostContext, frame: &wgpu::TextureView) {
+        let mut current = 0;
+        for entry in self.effects.iter_mut().filter(|entry| entry.enabled && entry.effect.stage() == Stage::Hdr) {
+            entry.effect.render(ctx, &self.hdr_targets[current], &self.hdr_targets[1 - current].view);
+            current = 1 - current;
+        }
+
+        let mut ldr = self
+            .effects
+            .iter_mut()
+            .filter(|entry| entry.enabled && entry.effect.stage() == Stage::Ldr)
+            .peekable();
+        let output = match ldr.peek() {
+            Some(_) => &self.ldr_targets[0].view,
+            None => frame,
+        };
+        self.tonemap.render(ctx, &self.hdr_targets[current], output);
+
+        let mut current = 0;
+        while let Some(entry) = ldr.next() {
+            let output = match ldr.peek() {
+                Some(_) => &self.ldr_targets[1 - current].view,
+                None => frame,
+            };
+            entry.effect.render(ctx, &self.ldr_targets[current], output);
+            current = 1 - current;
+        }
+    }
+}
+
+/// Entries for an input texture and its sampler, the first bind group of most effects.
+pub const INPUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    },
+];
+
+/// Binds a texture view and sampler with [`INPUT_ENTRIES`]. Inputs change between
+/// frames, so this is done every time an effect renders.
+pub fn input_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    view: &wgpu::TextureView,
+    sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ],
+        label: Some("post_input_bind_group"),
+    })
+}
+
+/// A pipeline drawing a fullscreen triangle with `shader`, which should include
+/// `fullscreen.wgsl` for its vertex stage.
+pub fn fullscreen_key(
+    label: &str,
+    shader: &str,
+    format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+) -> PipelineKey {
+    PipelineKey::new(label, shader)
+        .cull_mode(None)
+        .color_target(format, blend)
+}
+
+/// Draws `pipeline` over all of `output`. Passes that blend into what is already
+/// there load it, the others can clear.
+pub fn fullscreen_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    pipeline: &wgpu::RenderPipeline,
+    bind_groups: &[&wgpu::BindGroup],
+    output: &wgpu::TextureView,
+    load: wgpu::LoadOp<wgpu::Color>,
+) {
+    let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: output,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load,
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+    });
+    pass.set_pipeline(pipeline);
+    for (i, bind_group) in bind_groups.iter().enumerate() {
+        pass.set_bind_group(i as u32, bind_group, &[]);
+    }
+    pass.draw(0..3, 0..1);
+}
@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use super::super::{
+    buffer::Buffer,
+    pipelines::cache::{PipelineCache, PipelineKey},
+    settings::Tonemapper,
+    texture::Texture,
+};
+
+use super::{fullscreen_key, fullscreen_pass, input_bind_group, PostContext, INPUT_ENTRIES};
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct TonemapUniform {
+    exposure: f32,
+    tonemapper: u32,
+}
+
+impl TonemapUniform {
+    pub fn new(tonemapper: Tonemapper, exposure: f32) -> Self {
+        // Matches the TONEMAPPER_* defines in tonemap.wgsl.
+        let tonemapper = match tonemapper {
+            Tonemapper::Aces => 0,
+            Tonemapper::Reinhard => 1,
+            Tonemapper::AgX => 2,
+        };
+        Self { exposure, tonemapper }
+    }
+}
+
+/// Maps the HDR scene into the surface format, between the HDR and LDR effects.
+pub struct Tonemap {
+    pub tonemapper: Tonemapper,
+    /// Scene colors are multiplied by this before tonemapping.
+    pub exposure: f32,
+    pipeline: Arc<wgpu::RenderPipeline>,
+    input_layout: Arc<wgpu::BindGroupLayout>,
+    buffer: Buffer<TonemapUniform>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Tonemap {
+    pub const SHADER: &'static str = "shaders/tonemap.wgsl";
+
+    pub const ENTRIES: &'static [wgpu::BindGroupLayoutEntry] = &[
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+    ];
+
+    /// Surfaces that aren't sRGB get the output encoded in the shader.
+    pub fn key(format: wgpu::TextureFormat) -> PipelineKey {
+        let key = fullscreen_key("Tonemap", Self::SHADER, format, None)
+            .bind_group(INPUT_ENTRIES)
+            .bind_group(Self::ENTRIES);
+        if format.is_srgb() {
+            key
+        } else {
+            key.feature("SRGB_ENCODE")
+        }
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        format: wgpu::TextureFormat,
+        tonemapper: Tonemapper,
+        exposure: f32,
+    ) -> anyhow::Result<Self> {
+        let buffer = Buffer::new(device, wgpu::BufferUsages::UNIFORM, &[TonemapUniform::new(tonemapper, exposure)]);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &cache.bind_group_layout(device, Self::ENTRIES),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.buff.as_entire_binding(),
+            }],
+            label: Some("tonemap_bind_group"),
+        });
+        Ok(Self {
+            tonemapper,
+            exposure,
+            pipeline: cache.get(device, &Self::key(format))?,
+            input_layout: cache.bind_group_layout(device, INPUT_ENTRIES),
+            buffer,
+            bind_group,
+        })
+    }
+
+    pub fn create_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        format: wgpu::TextureFormat,
+    ) -> anyhow::Result<()> {
+        self.pipeline = cache.get(device, &Self::key(format))?;
+        Ok(())
+    }
+
+    /// Uploads `tonemapper` and `exposure` after they were changed.
+    pub fn update(&self, queue: &wgpu::Queue) {
+        let uniform = TonemapUniform::new(self.tonemapper, self.exposure);
+        queue.write_buffer(&self.buffer.buff, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    pub fn render(&self, ctx: &mut PostContext, input: &Texture, output: &wgpu::TextureView) {
+        let input = input_bind_group(ctx.device, &self.input_layout, &input.view, &input.sampler);
+        let bind_groups = [&input, &self.bind_group];
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        fullscreen_pass(ctx.encoder, "Tonemap Pass", &self.pipeline, &bind_groups, output, clear);
+    }
+}
@@ -0,0 +1,377 @@
+//! The WGSL preprocessor. Only depends on `std` and `anyhow`, so `build.rs` can
+//! include it to validate the shaders the same way they are loaded at runtime.
+
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Context};
+
+/// The file and line a line of preprocessed source came from.
+#[derive(Debug, Clone, Copy)]
+struct Origin {
+    file: usize,
+    line: usize,
+}
+
+/// Source after preprocessing, with a map back to the lines it came from.
+pub struct Preprocessed {
+    pub source: String,
+    /// Every file that went in, starting with the one preprocessing started from.
+    pub files: Vec<String>,
+    /// Names checked by `#ifdef` and `#ifndef`, i.e. the features that make a
+    /// difference to the output.
+    pub conditions: BTreeSet<String>,
+    lines: Vec<Origin>,
+}
+
+impl Preprocessed {
+    /// The file and line `line` (1-based) of the output came from.
+    pub fn origin(&self, line: usize) -> Option<(&str, usize)> {
+        let origin = self.lines.get(line.checked_sub(1)?)?;
+        Some((&self.files[origin.file], origin.line))
+    }
+
+    /// Rewrites `label:line` locations in a compiler message, which refer to the
+    /// output, to the file and line they came from.
+    pub fn map_locations(&self, label: &str, message: &str) -> String {
+        let pattern = format!("{}:", label);
+        let mut mapped = String::with_capacity(message.len());
+        let mut rest = message;
+        while let Some(start) = rest.find(&pattern) {
+            mapped.push_str(&rest[..start]);
+            let after = &rest[start + pattern.len()..];
+            let digits = after.chars().take_while(char::is_ascii_digit).count();
+            match after[..digits].parse().ok().and_then(|line| self.origin(line)) {
+                Some((file, line)) => {
+                    mapped.push_str(&format!("{}:{}", file, line));
+                    rest = &after[digits..];
+                }
+                None => {
+                    mapped.push_str(&pattern);
+                    rest = after;
+                }
+            }
+        }
+        mapped.push_str(rest);
+        mapped
+    }
+}
+
+/// Preprocesses `file_name` with `features` defined, reading files with `read`.
+///
+/// Supported directives, each on its own line:
+/// - `#include "file.wgsl"`, relative to the including file. Files are only
+///   included once, so shared structs can be included from several places.
+/// - `#define NAME [value]` and `#undef NAME`. Defines with a value are replaced
+///   by it wherever `NAME` appears as a word.
+/// - `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif`.
+pub fn preprocess(
+    file_name: &str,
+    features: &[&str],
+    read: &mut dyn FnMut(&str) -> anyhow::Result<String>,
+) -> anyhow::Result<Preprocessed> {
+    let mut preprocessor = Preprocessor {
+        read,
+        defines: features.iter().map(|f| (f.to_string(), String::new())).collect(),
+        stack: Vec::new(),
+        output: Preprocessed {
+            source: String::new(),
+            files: Vec::new(),
+            conditions: BTreeSet::new(),
+            lines: Vec::new(),
+        },
+    };
+    preprocessor.include(file_name)?;
+    Ok(preprocessor.output)
+}
+
+/// An `#ifdef`/`#ifndef` block the preprocessor is in.
+struct Condition {
+    active: bool,
+    seen_else: bool,
+    line: usize,
+}
+
+struct Preprocessor<'a> {
+    read: &'a mut dyn FnMut(&str) -> anyhow::Result<String>,
+    defines: HashMap<String, String>,
+    /// Files currently being included, to catch include cycles.
+    stack: Vec<String>,
+    output: Preprocessed,
+}
+
+impl Preprocessor<'_> {
+    fn include(&mut self, file_name: &str) -> anyhow::Result<()> {
+        if self.stack.iter().any(|f| f == file_name) {
+            bail!("{} includes itself through {}", file_name, self.stack.join(" -> "));
+        }
+        if self.output.files.iter().any(|f| f == file_name) {
+            return Ok(());
+        }
+
+        let text = (self.read)(file_name)?;
+        let file = self.output.files.len();
+        self.output.files.push(file_name.to_string());
+        self.stack.push(file_name.to_string());
+
+        let mut conditions: Vec<Condition> = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let location = format!("{}:{}", file_name, i + 1);
+            let active = conditions.iter().all(|c| c.active);
+
+            let Some(directive) = line.trim_start().strip_prefix('#') else {
+                if active {
+                    let line = self.substitute(line);
+                    self.output.source.push_str(&line);
+                    self.output.source.push('\n');
+                    self.output.lines.push(Origin { file, line: i + 1 });
+                }
+                continue;
+            };
+
+            let (name, argument) = directive
+                .trim_end()
+                .split_once(char::is_whitespace)
+                .map_or((directive.trim_end(), ""), |(name, argument)| (name, argument.trim()));
+            match name {
+                "ifdef" | "ifndef" => {
+                    let define = identifier(argument, &location)?;
+                    self.output.conditions.insert(define.to_string());
+                    conditions.push(Condition {
+                        active: self.defines.contains_key(define) == (name == "ifdef"),
+                        seen_else: false,
+                        line: i + 1,
+                    });
+                }
+                "else" => {
+                    let condition = conditions
+                        .last_mut()
+                        .filter(|c| !c.seen_else)
+                        .ok_or_else(|| anyhow!("{}: #else without #ifdef", location))?;
+                    condition.active = !condition.active;
+                    condition.seen_else = true;
+                }
+                "endif" => {
+                    conditions
+                        .pop()
+                        .ok_or_else(|| anyhow!("{}: #endif without #ifdef", location))?;
+                }
+                // Everything else is skipped inside inactive blocks.
+                _ if !active => {}
+                "define" => {
+                    let (define, value) = argument.split_once(char::is_whitespace).unwrap_or((argument, ""));
+                    let define = identifier(define, &location)?;
+                    self.defines.insert(define.to_string(), value.trim().to_string());
+                }
+                "undef" => {
+                    self.defines.remove(identifier(argument, &location)?);
+                }
+                "include" => {
+                    let path = argument
+                        .strip_prefix('"')
+                        .and_then(|a| a.strip_suffix('"'))
+                        .ok_or_else(|| anyhow!("{}: expected #include \"file\"", location))?;
+                    self.include(&sibling(file_name, path))
+                        .with_context(|| format!("included from {}", location))?;
+                }
+                _ => bail!("{}: unknown directive #{}", location, name),
+            }
+        }
+
+        if let Some(condition) = conditions.last() {
+            bail!("{}:{}: #ifdef without #endif", file_name, condition.line);
+        }
+        self.stack.pop();
+        Ok(())
+    }
+
+    /// Replaces defines that have a value, matching whole words only.
+    fn substitute(&self, line: &str) -> String {
+        if self.defines.values().all(String::is_empty) {
+            return line.to_string();
+        }
+
+        let mut result = String::with_capacity(line.len());
+        let mut word = String::new();
+        for c in line.chars().chain(std::iter::once('\n')) {
+            if c.is_alphanumeric() || c == '_' {
+                word.push(c);
+                continue;
+            }
+            match self.defines.get(&word) {
+                Some(value) if !value.is_empty() => result.push_str(value),
+                _ => result.push_str(&word),
+            }
+            word.clear();
+            if c != '\n' {
+                result.push(c);
+            }
+        }
+        result
+    }
+}
+
+/// Same as `common::resources::sibling`, which `build.rs` can't use.
+fn sibling(file_name: &str, other: &str) -> String {
+    match Path::new(file_name).parent() {
+        Some(parent) => parent.join(other).to_string_lossy().into_owned(),
+        None => other.to_string(),
+    }
+}
+
+fn identifier<'a>(name: &'a str, location: &str) -> anyhow::Result<&'a str> {
+    let valid = name.chars().next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if valid {
+        Ok(name)
+    } else {
+        Err(anyhow!("{}: invalid name {:?}", location, name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Preprocesses `main.wgsl` out of `files`, given as (name, text) pairs.
+    fn run(files: &[(&str, &str)], features: &[&str]) -> anyhow::Result<Preprocessed> {
+        let mut read = |file_name: &str| {
+            files
+                .iter()
+                .find(|(name, _)| *name == file_name)
+                .map(|(_, text)| text.to_string())
+                .ok_or_else(|| anyhow!("{} not found", file_name))
+        };
+        preprocess("main.wgsl", features, &mut read)
+    }
+
+    fn source(files: &[(&str, &str)], features: &[&str]) -> String {
+        run(files, features).unwrap().source
+    }
+
+    #[test]
+    fn files_are_included_once() {
+        let files = [
+            ("main.wgsl", "#include \"a.wgsl\"\n#include \"b.wgsl\"\nmain"),
+            ("a.wgsl", "#include \"common.wgsl\"\na"),
+            ("b.wgsl", "#include \"common.wgsl\"\nb"),
+            ("common.wgsl", "common"),
+        ];
+        let output = run(&files, &[]).unwrap();
+
+        assert_eq!(output.source, "common\na\nb\nmain\n");
+        assert_eq!(output.files, ["main.wgsl", "a.wgsl", "common.wgsl", "b.wgsl"]);
+    }
+
+    #[test]
+    fn includes_are_relative_to_including_file() {
+        let files = [
+            ("main.wgsl", "#include \"lib/a.wgsl\""),
+            ("lib/a.wgsl", "#include \"b.wgsl\""),
+            ("lib/b.wgsl", "b"),
+        ];
+
+        assert_eq!(source(&files, &[]), "b\n");
+    }
+
+    #[test]
+    fn include_cycle_is_an_error() {
+        let files = [
+            ("main.wgsl", "#include \"a.wgsl\""),
+            ("a.wgsl", "#include \"b.wgsl\""),
+            ("b.wgsl", "#include \"a.wgsl\""),
+        ];
+        let error = format!("{:#}", run(&files, &[]).err().unwrap());
+
+        assert!(error.contains("a.wgsl includes itself through main.wgsl -> a.wgsl -> b.wgsl"), "{}", error);
+    }
+
+    #[test]
+    fn nested_conditions() {
+        let text = "\
+#ifdef A
+a
+#ifndef B
+not b
+#else
+b
+#endif
+#else
+not a
+#endif
+end";
+        let files = [("main.wgsl", text)];
+
+        assert_eq!(source(&files, &[]), "not a\nend\n");
+        assert_eq!(source(&files, &["A"]), "a\nnot b\nend\n");
+        assert_eq!(source(&files, &["A", "B"]), "a\nb\nend\n");
+        assert_eq!(source(&files, &["B"]), "not a\nend\n");
+        assert_eq!(
+            run(&files, &[]).unwrap().conditions.into_iter().collect::<Vec<_>>(),
+            ["A", "B"]
+        );
+    }
+
+    #[test]
+    fn directives_in_inactive_blocks_are_skipped() {
+        let files = [("main.wgsl", "#ifdef A\n#include \"missing.wgsl\"\n#define B\n#endif\n#ifdef B\nb\n#endif")];
+
+        assert_eq!(source(&files, &[]), "");
+    }
+
+    #[test]
+    fn unbalanced_directives_are_errors() {
+        let error = |text: &str| format!("{:#}", run(&[("main.wgsl", text)], &[]).err().unwrap());
+
+        assert_eq!(error("#ifdef A\na"), "main.wgsl:1: #ifdef without #endif");
+        assert_eq!(error("a\n#endif"), "main.wgsl:2: #endif without #ifdef");
+        assert_eq!(error("#else"), "main.wgsl:1: #else without #ifdef");
+        assert_eq!(error("#ifdef A\n#else\n#else\n#endif"), "main.wgsl:3: #else without #ifdef");
+        assert_eq!(error("#iffy A"), "main.wgsl:1: unknown directive #iffy");
+    }
+
+    #[test]
+    fn defines_replace_whole_words() {
+        let files = [("main.wgsl", "#define COUNT 4u\nlet a = COUNT;\nlet b = COUNTS + MY_COUNT + COUNT_2;\nf(COUNT,COUNT)")];
+
+        assert_eq!(
+            source(&files, &[]),
+            "let a = 4u;\nlet b = COUNTS + MY_COUNT + COUNT_2;\nf(4u,4u)\n"
+        );
+    }
+
+    #[test]
+    fn undef_removes_define() {
+        let files = [("main.wgsl", "#define N 1\nN\n#undef N\nN\n#ifdef N\ndefined\n#endif")];
+
+        assert_eq!(source(&files, &[]), "1\nN\n");
+    }
+
+    #[test]
+    fn output_lines_map_to_their_file() {
+        let files = [
+            ("main.wgsl", "#ifdef A\nskipped\n#endif\n#include \"lib.wgsl\"\nmain"),
+            ("lib.wgsl", "// lib\nlib"),
+        ];
+        let output = run(&files, &[]).unwrap();
+
+        assert_eq!(output.source, "// lib\nlib\nmain\n");
+        assert_eq!(output.origin(1), Some(("lib.wgsl", 1)));
+        assert_eq!(output.origin(2), Some(("lib.wgsl", 2)));
+        assert_eq!(output.origin(3), Some(("main.wgsl", 5)));
+        assert_eq!(output.origin(0), None);
+        assert_eq!(output.origin(4), None);
+        assert_eq!(
+            output.map_locations("shader", "error at shader:2:5 and shader:3:1, not shader:9 or other:1"),
+            "error at lib.wgsl:2:5 and main.wgsl:5:1, not shader:9 or other:1"
+        );
+    }
+
+    #[test]
+    fn errors_in_included_files_name_both_locations() {
+        let files = [("main.wgsl", "a\n#include \"lib.wgsl\""), ("lib.wgsl", "#define 1X")];
+        let error = format!("{:#}", run(&files, &[]).err().unwrap());
+
+        assert_eq!(error, "included from main.wgsl:2: lib.wgsl:1: invalid name \"1X\"");
+    }
+}
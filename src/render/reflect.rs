@@ -0,0 +1,314 @@
+//! Checks the layouts a pipeline is built with against what its shader declares,
+//! so a binding or vertex attribute that got out of sync fails with a clear
+//! message instead of a wgpu validation error, or worse, garbage on screen.
+
+use anyhow::{anyhow, bail};
+use naga::{AddressSpace, ImageClass, ImageDimension, ScalarKind, ShaderStage, TypeInner};
+
+/// Checks that `groups[i]` provides every binding of group `i` the given entry
+/// points use, with a matching type and visible to the stages using it.
+pub fn check_bind_groups(
+    module: &naga::Module,
+    info: &naga::valid::ModuleInfo,
+    entry_points: &[&str],
+    groups: &[&[wgpu::BindGroupLayoutEntry]],
+) -> anyhow::Result<()> {
+    for (handle, var) in module.global_variables.iter() {
+        let Some(binding) = &var.binding else {
+            continue;
+        };
+        let name = var.name.as_deref().unwrap_or("<unnamed>");
+
+        let mut stages = wgpu::ShaderStages::NONE;
+        for (i, entry_point) in module.entry_points.iter().enumerate() {
+            if entry_points.contains(&entry_point.name.as_str()) && !info.get_entry_point(i)[handle].is_empty() {
+                stages |= match entry_point.stage {
+                    ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+                    ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+                    ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+                };
+            }
+        }
+        // Bindings the pipeline's entry points don't use don't need to be in the layout.
+        if stages.is_empty() {
+            continue;
+        }
+
+        let entries = groups.get(binding.group as usize).ok_or_else(|| {
+            anyhow!(
+                "`{}` is in group {}, but the pipeline only has {} bind groups",
+                name,
+                binding.group,
+                groups.len()
+            )
+        })?;
+        let entry = entries
+            .iter()
+            .find(|entry| entry.binding == binding.binding)
+            .ok_or_else(|| {
+                anyhow!(
+                    "`{}` is at @group({}) @binding({}), which the bind group layout doesn't have",
+                    name,
+                    binding.group,
+                    binding.binding
+                )
+            })?;
+
+        if !binding_matches(module, var, &entry.ty) {
+            bail!(
+                "`{}` at @group({}) @binding({}) is a {:?} in the shader, but {:?} in the layout",
+                name,
+                binding.group,
+                binding.binding,
+                module.types[var.ty].inner,
+                entry.ty
+            );
+        }
+        if !entry.visibility.contains(stages) {
+            bail!(
+                "`{}` at @group({}) @binding({}) is used in {:?}, but only visible to {:?}",
+                name,
+                binding.group,
+                binding.binding,
+                stages,
+                entry.visibility
+            );
+        }
+    }
+    Ok(())
+}
+
+fn binding_matches(module: &naga::Module, var: &naga::GlobalVariable, ty: &wgpu::BindingType) -> bool {
+    match (&module.types[var.ty].inner, ty) {
+        (_, wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, .. }) => {
+            var.space == AddressSpace::Uniform
+        }
+        (_, wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Storage { read_only }, .. }) => {
+            match var.space {
+                AddressSpace::Storage { access } => !read_only || !access.contains(naga::StorageAccess::STORE),
+                _ => false,
+            }
+        }
+        (
+            TypeInner::Image { dim, arrayed, class },
+            wgpu::BindingType::Texture { view_dimension, multisampled, sample_type },
+        ) => {
+            let class_matches = match (class, sample_type) {
+                (ImageClass::Sampled { kind, multi }, _) => {
+                    multi == multisampled
+                        && match sample_type {
+                            wgpu::TextureSampleType::Float { .. } | wgpu::TextureSampleType::Depth => {
+                                *kind == ScalarKind::Float
+                            }
+                            wgpu::TextureSampleType::Sint => *kind == ScalarKind::Sint,
+                            wgpu::TextureSampleType::Uint => *kind == ScalarKind::Uint,
+                        }
+                }
+                (ImageClass::Depth { multi }, wgpu::TextureSampleType::Depth) => multi == multisampled,
+                _ => false,
+            };
+            class_matches && view_dimension_matches(*dim, *arrayed, *view_dimension)
+        }
+        (
+            TypeInner::Image { dim, arrayed, class: ImageClass::Storage { .. } },
+            wgpu::BindingType::StorageTexture { view_dimension, .. },
+        ) => view_dimension_matches(*dim, *arrayed, *view_dimension),
+        (TypeInner::Sampler { comparison }, wgpu::BindingType::Sampler(sampler)) => {
+            *comparison == (*sampler == wgpu::SamplerBindingType::Comparison)
+        }
+        _ => false,
+    }
+}
+
+fn view_dimension_matches(dim: ImageDimension, arrayed: bool, view: wgpu::TextureViewDimension) -> bool {
+    use wgpu::TextureViewDimension as View;
+    matches!(
+        (dim, arrayed, view),
+        (ImageDimension::D1, false, View::D1)
+            | (ImageDimension::D2, false, View::D2)
+            | (ImageDimension::D2, true, View::D2Array)
+            | (ImageDimension::D3, false, View::D3)
+            | (ImageDimension::Cube, false, View::Cube)
+            | (ImageDimension::Cube, true, View::CubeArray)
+    )
+}
+
+/// Checks that `buffers` provide every `@location` input of the vertex entry point
+/// `entry_point`, with attributes of the same scalar type.
+pub fn check_vertex_buffers(
+    module: &naga::Module,
+    entry_point: &str,
+    buffers: &[wgpu::VertexBufferLayout],
+) -> anyhow::Result<()> {
+    let function = &module
+        .entry_points
+        .iter()
+        .find(|ep| ep.name == entry_point && ep.stage == ShaderStage::Vertex)
+        .ok_or_else(|| anyhow!("The shader has no vertex entry point `{}`", entry_point))?
+        .function;
+
+    // Inputs are either arguments or members of struct arguments.
+    let mut inputs = Vec::new();
+    for argument in &function.arguments {
+        match &module.types[argument.ty].inner {
+            TypeInner::Struct { members, .. } => {
+                for member in members {
+                    inputs.push((member.name.as_deref(), &member.binding, member.ty));
+                }
+            }
+            _ => inputs.push((argument.name.as_deref(), &argument.binding, argument.ty)),
+        }
+    }
+
+    for (name, binding, ty) in inputs {
+        let Some(naga::Binding::Location { location, .. }) = binding else {
+            continue;
+        };
+        let name = name.unwrap_or("<unnamed>");
+        let attribute = buffers
+            .iter()
+            .flat_map(|buffer| buffer.attributes)
+            .find(|attribute| attribute.shader_location == *location)
+            .ok_or_else(|| anyhow!("`{}` is at @location({}), which no vertex buffer provides", name, location))?;
+
+        let kind = match module.types[ty].inner {
+            TypeInner::Scalar { kind, .. } | TypeInner::Vector { kind, .. } => kind,
+            ref other => bail!("`{}` at @location({}) has unsupported type {:?}", name, location, other),
+        };
+        if kind != format_kind(attribute.format) {
+            bail!(
+                "`{}` at @location({}) is {:?} in the shader, but the attribute is {:?}",
+                name,
+                location,
+                kind,
+                attribute.format
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The scalar type a vertex format is read as in the shader.
+fn format_kind(format: wgpu::VertexFormat) -> ScalarKind {
+    use wgpu::VertexFormat::*;
+    match format {
+        Uint8x2 | Uint8x4 | Uint16x2 | Uint16x4 | Uint32 | Uint32x2 | Uint32x3 | Uint32x4 => ScalarKind::Uint,
+        Sint8x2 | Sint8x4 | Sint16x2 | Sint16x4 | Sint32 | Sint32x2 | Sint32x3 | Sint32x4 => ScalarKind::Sint,
+        // Float and normalized formats.
+        _ => ScalarKind::Float,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::model_obj::{AlphaMode, ModelVertex};
+    use crate::render::pipelines::cache::PipelineKey;
+    use crate::render::pipelines::figure::{FigureLayout, FigurePipeline};
+    use crate::render::pipelines::lighting::LightingLayout;
+    use crate::render::pipelines::skybox::Skybox;
+    use crate::render::shader::Shader;
+    use crate::render::Vertex;
+    use crate::scene::camera::CameraLayout;
+
+    const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+    fn parse(file_name: &str, features: &[&str]) -> (naga::Module, naga::valid::ModuleInfo) {
+        Shader::load(file_name, features).unwrap().parse().unwrap()
+    }
+
+    /// Runs the checks the pipeline cache runs before creating `key`'s pipeline.
+    fn check_key(key: &PipelineKey) -> anyhow::Result<()> {
+        let features = key.features.iter().map(String::as_str).collect::<Vec<_>>();
+        let (module, info) = parse(&key.shader, &features);
+        let groups = key.bind_groups.iter().map(Vec::as_slice).collect::<Vec<_>>();
+        let entry_points = std::iter::once(&key.vertex_entry)
+            .chain(&key.fragment_entry)
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        let buffers = key
+            .vertex_layouts
+            .iter()
+            .map(|layout| wgpu::VertexBufferLayout {
+                array_stride: layout.array_stride,
+                step_mode: layout.step_mode,
+                attributes: &layout.attributes,
+            })
+            .collect::<Vec<_>>();
+        check_bind_groups(&module, &info, &entry_points, &groups)?;
+        check_vertex_buffers(&module, &key.vertex_entry, &buffers)
+    }
+
+    #[test]
+    fn figure_layouts_match_shader() {
+        let (module, info) = parse(FigurePipeline::SHADER, &[]);
+        let groups = [FigureLayout::ENTRIES, CameraLayout::ENTRIES, LightingLayout::ENTRIES];
+
+        check_bind_groups(&module, &info, &["vs_main", "fs_main"], &groups).unwrap();
+        check_vertex_buffers(&module, "vs_main", &[ModelVertex::desc()]).unwrap();
+    }
+
+    #[test]
+    fn sky_layouts_match_shader() {
+        let (module, info) = parse(Skybox::SHADER, &[]);
+
+        check_bind_groups(&module, &info, &["vs_main", "fs_main"], &[Skybox::ENTRIES, CameraLayout::ENTRIES]).unwrap();
+        check_vertex_buffers(&module, "vs_main", &[]).unwrap();
+    }
+
+    #[test]
+    fn every_figure_pipeline_matches_shader() {
+        for sample_count in [1, 4] {
+            for alpha_mode in [AlphaMode::Opaque, AlphaMode::Mask, AlphaMode::Blend] {
+                let key = FigurePipeline::key(FORMAT, wgpu::PolygonMode::Fill, alpha_mode, sample_count);
+                check_key(&key).unwrap_or_else(|e| panic!("{:?}: {:#}", key.features, e));
+                check_key(&FigurePipeline::depth_key(alpha_mode, sample_count)).unwrap();
+            }
+            check_key(&FigurePipeline::key(FORMAT, wgpu::PolygonMode::Line, AlphaMode::Opaque, sample_count)).unwrap();
+            check_key(&FigurePipeline::oit_key(sample_count)).unwrap();
+            check_key(&Skybox::key(FORMAT, sample_count)).unwrap();
+        }
+    }
+
+    #[test]
+    fn missing_binding_is_an_error() {
+        let (module, info) = parse(FigurePipeline::SHADER, &[]);
+        let material = FigureLayout::ENTRIES.iter().filter(|entry| entry.binding != 2).copied().collect::<Vec<_>>();
+        let groups = [material.as_slice(), CameraLayout::ENTRIES, LightingLayout::ENTRIES];
+
+        let error = check_bind_groups(&module, &info, &["vs_main", "fs_main"], &groups).unwrap_err();
+        assert!(error.to_string().contains("@group(0) @binding(2)"), "{}", error);
+    }
+
+    #[test]
+    fn wrong_binding_type_is_an_error() {
+        let (module, info) = parse(Skybox::SHADER, &[]);
+        let mut sky = Skybox::ENTRIES.to_vec();
+        sky[1].ty = wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison);
+
+        let error = check_bind_groups(&module, &info, &["vs_main", "fs_main"], &[&sky, CameraLayout::ENTRIES]).unwrap_err();
+        assert!(error.to_string().contains("@group(0) @binding(1)"), "{}", error);
+    }
+
+    #[test]
+    fn hidden_binding_is_an_error() {
+        let (module, info) = parse(FigurePipeline::SHADER, &[]);
+        let mut camera = CameraLayout::ENTRIES.to_vec();
+        camera[0].visibility = wgpu::ShaderStages::FRAGMENT;
+        let groups = [FigureLayout::ENTRIES, &camera, LightingLayout::ENTRIES];
+
+        let error = check_bind_groups(&module, &info, &["vs_main", "fs_main"], &groups).unwrap_err();
+        assert!(error.to_string().contains("only visible to"), "{}", error);
+    }
+
+    #[test]
+    fn wrong_vertex_attribute_is_an_error() {
+        let (module, _) = parse(FigurePipeline::SHADER, &[]);
+        let mut attributes = ModelVertex::ATTRIBUTES.to_vec();
+        attributes[0].format = wgpu::VertexFormat::Uint32x3;
+        let buffer = wgpu::VertexBufferLayout { attributes: &attributes, ..ModelVertex::desc() };
+
+        assert!(check_vertex_buffers(&module, "vs_main", &[buffer]).is_err());
+        assert!(check_vertex_buffers(&module, "vs_main", &[]).is_err());
+    }
+}
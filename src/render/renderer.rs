@@ -1,3 +1,4 @@
+use anyhow::Context;
 use wgpu::{BindGroup, Instance};
 use winit::{
     event::*,
@@ -5,17 +6,45 @@ use winit::{
 };
 use cgmath::prelude::*;
 use crate::{render::{
-    pipelines::figure::{FigureVertex, FigurePipeline, FigureLayout, Instance as FigureInstance},
+    pipelines::{
+        cache::PipelineCache,
+        figure::{FigureVertex, FigurePipelines, FigureLayout, Instance as FigureInstance},
+        lighting::LightingLayout,
+        skybox::Skybox,
+    },
+    fog::Fog,
+    ibl::Environment,
+    oit::WeightedBlended,
+    sun::Sun,
+    post::{bloom::Bloom, fxaa::Fxaa, tonemap::Tonemap, PostContext, PostProcessing},
+    settings::{self, FogMode, PostAntiAliasing, RenderSettings, Transparency},
+    ssao::Ssao,
     texture::Texture,
     mesh::{Mesh, Quad, Cube},
     model::Model,
     buffer::Buffer
     
-}, scene::camera::{Camera, CameraUniform, Projection, CameraLayout, CameraController}};
+}, scene::{camera::{Camera, CameraUniform, Projection, CameraLayout, CameraController}, SceneSettings, Sky}};
+use crate::input::{
+    bindings::{Action, InputBindings, InputMap},
+    event::InputEvent,
+};
+
+use super::model_obj::{AlphaMode, DrawModel, self};
+
+use crate::common::{
+    assets::{AssetServer, Handle, LoadState},
+    resources,
+    watcher::AssetWatcher,
+};
 
-use super::model_obj::{DrawModel, self};
 
-use crate::common::resources;
+/// Where frames end up: the window surface, or an offscreen texture when
+/// running headless.
+enum RenderTarget {
+    Surface(wgpu::Surface),
+    Offscreen(Texture),
+}
 
 /// State gestiona los recursos de renderizado de la aplicación,
 /// actualmente para un triángulo. Con la expansión del proyecto,
@@ -23,33 +52,84 @@ use crate::common::resources;
 /// alcance más amplio.
 pub struct State {
     camera: Camera,
+    /// Camera as it was before the last update, rendering interpolates from it.
+    previous_camera: Camera,
     projection: Projection,
     camera_uniform: CameraUniform,
     camera_buffer: Buffer<CameraUniform>,
     instance_buffer: Buffer<FigureInstance>,
     pub camera_controller: CameraController,
     camera_bind_group: wgpu::BindGroup,
-    surface: wgpu::Surface,
+    target: RenderTarget,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     pub size: winit::dpi::PhysicalSize<u32>,
-    pub window: Window,
-    quad_pipeline:FigurePipeline,
+    /// `None` when rendering headless, which doesn't need a display.
+    window: Option<Window>,
+    figure_pipelines: FigurePipelines,
+    wireframe: bool,
     quad_model: Model<FigureVertex>,
     instances: Vec<FigureInstance>,
-    diffuse_bind_group: wgpu::BindGroup,
+    figure_layout: FigureLayout,
+    lighting_layout: LightingLayout,
+    lighting_bind_group: wgpu::BindGroup,
+    ssao: Ssao,
+    fog: Fog,
+    transparency: Transparency,
+    /// Targets and composite for `Transparency::WeightedBlended`, enabled while
+    /// it is selected.
+    oit: WeightedBlended,
+    /// Shown where nothing is drawn, black behind a skybox.
+    sky_color: [f32; 3],
+    /// `None` when the sky is a plain color.
+    skybox: Option<Skybox>,
+    /// Kept with the sun for the lighting bind group, which is recreated on resize.
+    environment: Environment,
+    sun: Sun,
     depth_texture: Texture,
-    diffuse_texture: Texture, //for later usage
+    /// Multisampled color target resolved into the scene target, `None` without MSAA.
+    msaa_target: Option<Texture>,
+    sample_count: u32,
+    /// Sample counts the surface and depth formats support, for cycling at runtime.
+    sample_counts: Vec<u32>,
+    /// Effects and tonemapping between the scene and the frame.
+    post: PostProcessing,
+    post_anti_aliasing: PostAntiAliasing,
     pub mouse_pressed: bool,
-    obj_model: model_obj::Model
-    
+    pub quit_requested: bool,
+    input_map: InputMap,
+    assets: AssetServer,
+    obj_model: Handle<model_obj::Model>,
+    /// Only set when hot reloading is enabled.
+    watcher: Option<AssetWatcher>,
+    pipelines: PipelineCache,
 }
  
 impl State {
-    pub async fn new(window: Window) -> Self {
-        let camera_controller = CameraController::new(4.0, 2.0);
+    /// Renders into the surface of `window`.
+    pub async fn new(window: Window, settings: &RenderSettings, scene: &SceneSettings) -> Self {
         let size = window.inner_size();
+        Self::create(Some(window), size, settings, scene).await
+    }
+
+    /// Renders into an offscreen texture of `size`, from the adapter and device
+    /// alone, so no window or display is needed.
+    pub async fn headless(
+        size: winit::dpi::PhysicalSize<u32>,
+        settings: &RenderSettings,
+        scene: &SceneSettings,
+    ) -> Self {
+        Self::create(None, size, settings, scene).await
+    }
+
+    async fn create(
+        window: Option<Window>,
+        size: winit::dpi::PhysicalSize<u32>,
+        settings: &RenderSettings,
+        scene: &SceneSettings,
+    ) -> Self {
+        let camera_controller = CameraController::new(4.0, 2.0);
 
 
         const NUM_INSTANCES_PER_ROW: u32 = 10;
@@ -76,29 +156,37 @@ impl State {
         // The instance is a handle to our GPU
         // BackendBit::PRIMARY => Vulkan + Metal + DX12 + Browser WebGPU
         let wgpu_instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
+            backends: settings.backends,
             ..Default::default()
         });
 
         // # Safety
         // The surface needs to live as long as the window that created it.
         // State owns the window so this should be safe.
-        let surface = unsafe { wgpu_instance.create_surface(&window) }.unwrap();
+        let surface = window
+            .as_ref()
+            .map(|window| unsafe { wgpu_instance.create_surface(window) }.unwrap());
 
         let adapter = wgpu_instance
             .request_adapter(&wgpu::RequestAdapterOptions {
                 power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
+                compatible_surface: surface.as_ref(),
                 force_fallback_adapter: false,
             })
             .await
-            .unwrap();
+            .unwrap_or_else(|| panic!("No graphics adapter found for backends {:?}", settings.backends));
+        log::info!("Using adapter {:?}", adapter.get_info());
+
+        // Wireframe rendering is optional, only ask for it if the adapter has it.
+        // Adapter specific format features allow sample counts other than 1 and 4.
+        let features = adapter.features()
+            & (wgpu::Features::POLYGON_MODE_LINE | wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES);
 
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    features: wgpu::Features::empty(),
+                    features,
                     limits: wgpu::Limits::default(),
                 },
                 None,
@@ -108,10 +196,19 @@ impl State {
 
         let instance_buffer = Buffer::new(&device, wgpu::BufferUsages::VERTEX, &instances);
         
-        let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an Srgb surface texture. Using a different
-        // one will result all the colors comming out darker. If you want to support non
-        // Srgb surfaces, you'll need to account for that when drawing to the frame.
+        // Headless rendering has no surface to ask, so it goes with what a surface
+        // would most likely give us.
+        let surface_caps = match &surface {
+            Some(surface) => surface.get_capabilities(&adapter),
+            None => wgpu::SurfaceCapabilities {
+                formats: vec![wgpu::TextureFormat::Rgba8UnormSrgb],
+                present_modes: vec![wgpu::PresentMode::Fifo],
+                alpha_modes: vec![wgpu::CompositeAlphaMode::Opaque],
+                usages: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            },
+        };
+        // Prefer an Srgb surface texture, so it encodes the tonemapped colors for us.
+        // Other surfaces get them encoded by the tonemap shader.
         let surface_format = surface_caps
             .formats
             .iter()
@@ -124,16 +221,26 @@ impl State {
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
+            present_mode: settings.present_mode.select(&surface_caps.present_modes),
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
         };
-        surface.configure(&device, &config);
+        let sample_counts = Self::supported_sample_counts(&adapter, &device);
+        let sample_count = settings::select_sample_count(settings.sample_count, &sample_counts);
+
+        let target = match surface {
+            Some(surface) => {
+                surface.configure(&device, &config);
+                RenderTarget::Surface(surface)
+            }
+            None => RenderTarget::Offscreen(Texture::create_render_target(&device, &config, "offscreen_target")),
+        };
 
-        let camera = Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
+        let camera = Camera::new(scene.camera_position, scene.camera_yaw, scene.camera_pitch);
         let projection = Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 100.0);
 
-        let camera_layout = CameraLayout::new(&device);
+        let mut pipelines = PipelineCache::new();
+        let camera_layout = CameraLayout::new(&device, &mut pipelines);
 
         let mut camera_uniform = CameraUniform::new();
         camera_uniform.update_view_proj(&camera, &projection);
@@ -154,29 +261,12 @@ impl State {
         });
 
 
-        let diffuse_bytes = include_bytes!("../../assets/images/dirt.png");
-        let diffuse_texture = Texture::from_bytes(&device, &queue, diffuse_bytes, "dirt.png").unwrap();
-        let depth_texture = Texture::create_depth_texture(&device, &config, "depth_texture");
-        let figure_layout = FigureLayout::new(&device);
-
-        let diffuse_bind_group = device.create_bind_group(
-            &wgpu::BindGroupDescriptor {
-                layout: &figure_layout.bind_group_layout,
-                entries: &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                    }
-                ],
-                label: Some("diffuse_bind_group"),
-            }
-        );
-
-        let shader = device.create_shader_module(wgpu::include_wgsl!("../../assets/shaders/shader.wgsl"));
+        let depth_texture = Texture::create_depth_texture(&device, &config, sample_count, "depth_texture");
+        let msaa_target =
+            (sample_count > 1).then(|| {
+                Texture::create_msaa_target(&device, &config, Texture::HDR_FORMAT, sample_count, "msaa_target")
+            });
+        let figure_layout = FigureLayout::new(&device, &mut pipelines);
 
         let mut quad_mesh = Mesh::new();
 
@@ -212,10 +302,15 @@ impl State {
         let cube = Cube::new(a, b, c, d, e, f, g, h);
 
 
-        let obj_model =
-        resources::load_model("cube.obj", &device, &queue, &figure_layout.bind_group_layout)
-            .await
-            .unwrap();
+        let mut assets = AssetServer::new(&device, &queue, &figure_layout.bind_group_layout);
+        let obj_model = assets.load_model(&scene.model);
+        // Offscreen frames are usually saved, so they shouldn't show placeholders.
+        if window.is_none() {
+            assets.finish_loading(&device, &queue, &figure_layout.bind_group_layout);
+            if let LoadState::Failed(e) = assets.load_state(&obj_model) {
+                log::warn!("Rendering a placeholder instead of {:?}: {}", scene.model, e);
+            }
+        }
  
 
 
@@ -226,15 +321,83 @@ impl State {
 
         let quad_model = Model::new(&device, &quad_mesh).unwrap();
 
-        let quad_pipeline: FigurePipeline = FigurePipeline::new(
+        let figure_pipelines = FigurePipelines::new(&device, &mut pipelines, Texture::HDR_FORMAT, sample_count)
+            .unwrap_or_else(|e| panic!("Could not build the figure pipelines: {:#}", e));
+
+        let ssao = Ssao::new(
             &device,
-            &shader,
+            &queue,
+            &mut pipelines,
             &config,
-            &figure_layout,
-            &camera_layout //temporary until i add global layouts
-        );
+            sample_count,
+            projection.calc_matrix(),
+            settings.ssao,
+        )
+        .unwrap_or_else(|e| panic!("Could not build the SSAO pipelines: {:#}", e));
+        let mut oit = WeightedBlended::new(&device, &mut pipelines)
+            .unwrap_or_else(|e| panic!("Could not build the OIT pipeline: {:#}", e));
+        let weighted_blended = settings.transparency == Transparency::WeightedBlended;
+        oit.set_enabled(&device, &config, sample_count, weighted_blended);
+        let sky = match Self::load_sky(&device, &queue, &mut pipelines, &scene.sky).await {
+            Ok(texture) => texture,
+            Err(e) => {
+                log::warn!("Could not load the sky {:?}, using a plain color: {:#}", scene.sky, e);
+                None
+            }
+        };
+        // The sky lights the scene, a plain color evenly from all around.
+        let fog = Fog::new(&device, settings.fog);
+        let (sky_color, skybox, environment) = match sky {
+            Some(texture) => {
+                let skybox = Skybox::new(&device, &mut pipelines, &texture, &fog, Texture::HDR_FORMAT, sample_count)
+                    .unwrap_or_else(|e| panic!("Could not build the skybox pipeline: {:#}", e));
+                let environment = Environment::new(&device, &queue, &mut pipelines, &texture);
+                ([0.0; 3], Some(skybox), environment)
+            }
+            None => {
+                let color = match scene.sky {
+                    Sky::Color(color) => color,
+                    _ => Sky::DEFAULT_COLOR,
+                };
+                let environment = Environment::from_color(&device, &queue, &mut pipelines, color);
+                (color, None, environment)
+            }
+        };
+        let environment =
+            environment.unwrap_or_else(|e| panic!("Could not build the image-based lighting pipelines: {:#}", e));
+        let lighting_layout = LightingLayout::new(&device, &mut pipelines);
+        let sun = Sun::new(&device, &scene.sun);
+        let lighting_bind_group = lighting_layout.bind_group(&device, ssao.occlusion(), &fog, &environment, &sun);
+
+        let tonemap = Tonemap::new(&device, &mut pipelines, config.format, settings.tonemapper, settings.exposure)
+            .unwrap_or_else(|e| panic!("Could not build the tonemap pipeline: {:#}", e));
+        let mut post = PostProcessing::new(&device, &config, tonemap);
+        let bloom = Bloom::new(&device, &mut pipelines, &config, settings.bloom)
+            .unwrap_or_else(|e| panic!("Could not build the bloom pipelines: {:#}", e));
+        post.add(bloom);
+        post.set_enabled(Bloom::NAME, settings.bloom.enabled);
+        let fxaa = Fxaa::new(&device, &mut pipelines, config.format)
+            .unwrap_or_else(|e| panic!("Could not build the FXAA pipeline: {:#}", e));
+        post.add(fxaa);
+        Self::set_post_anti_aliasing(&mut post, settings.post_anti_aliasing);
+
+        let watcher = if settings.hot_reload {
+            AssetWatcher::new()
+                .map_err(|e| log::warn!("Hot reloading is disabled, could not watch the assets: {:?}", e))
+                .ok()
+        } else {
+            None
+        };
+
+        let bindings = InputBindings::load(InputBindings::FILE_NAME)
+            .await
+            .unwrap_or_else(|e| {
+                log::warn!("Could not load input bindings, using the defaults: {:?}", e);
+                InputBindings::default()
+            });
 
         Self {
+            previous_camera: camera.clone(),
             camera,
             camera_uniform,
             camera_buffer,
@@ -243,98 +406,498 @@ impl State {
             instance_buffer,
             camera_controller,
             camera_bind_group,
-            surface,
+            target,
             device,
             queue,
             config,
             size,
             window,
-            quad_pipeline,
+            figure_pipelines,
+            wireframe: false,
             quad_model,
-            diffuse_bind_group,
-            diffuse_texture,
+            figure_layout,
+            lighting_layout,
+            lighting_bind_group,
+            ssao,
+            fog,
+            transparency: settings.transparency,
+            oit,
+            sky_color,
+            skybox,
+            environment,
+            sun,
             depth_texture,
+            msaa_target,
+            sample_count,
+            sample_counts,
+            post,
+            post_anti_aliasing: settings.post_anti_aliasing,
             mouse_pressed: false, // NEW!
-            obj_model
+            quit_requested: false,
+            input_map: InputMap::new(bindings),
+            assets,
+            obj_model,
+            watcher,
+            pipelines,
+        }
+    }
+
+    /// Sample counts both the HDR and the depth format can be rendered and
+    /// resolved with. Without adapter specific format features, only the counts
+    /// WebGPU guarantees are usable.
+    fn supported_sample_counts(adapter: &wgpu::Adapter, device: &wgpu::Device) -> Vec<u32> {
+        let format_features = |format: wgpu::TextureFormat| {
+            if device.features().contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+                adapter.get_texture_format_features(format).flags
+            } else {
+                format.guaranteed_format_features(device.features()).flags
+            }
+        };
+        let color = format_features(Texture::HDR_FORMAT);
+        let depth = format_features(Texture::DEPTH_FORMAT);
+        settings::SAMPLE_COUNTS
+            .into_iter()
+            .filter(|&count| {
+                count == 1
+                    || (color.sample_count_supported(count)
+                        && color.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE)
+                        && depth.sample_count_supported(count))
+            })
+            .collect()
+    }
+
+    /// Applies changes to the watched asset files. Changes are picked up when a
+    /// frame is rendered, so in low power mode they wait for the next redraw.
+    fn hot_reload(&mut self) {
+        let Some(watcher) = &self.watcher else {
+            return;
+        };
+        let changed = watcher.changed();
+        if changed.is_empty() {
+            return;
+        }
+
+        let shader_paths = self
+            .pipelines
+            .shader_files()
+            .iter()
+            .filter_map(|file| resources::resolve(file).ok()?.canonicalize().ok())
+            .collect::<Vec<_>>();
+        let mut shader_changed = false;
+        for path in changed {
+            if shader_paths.contains(&path) {
+                shader_changed = true;
+            } else if !self.assets.reload(&path) {
+                log::debug!("Ignoring change to {:?}, no asset uses it", path);
+            }
+        }
+        if shader_changed {
+            self.reload_shaders();
+        }
+    }
+
+    /// Rebuilds the pipelines from the shader files. If a shader doesn't validate,
+    /// the error is logged and the old pipelines stay in use.
+    fn reload_shaders(&mut self) {
+        let result = self.pipelines.rebuild(&self.device).and_then(|()| {
+            let figures =
+                FigurePipelines::new(&self.device, &mut self.pipelines, Texture::HDR_FORMAT, self.sample_count)?;
+            self.ssao.create_pipelines(&self.device, &mut self.pipelines, self.sample_count)?;
+            if let Some(skybox) = &mut self.skybox {
+                skybox.create_pipelines(&self.device, &mut self.pipelines, Texture::HDR_FORMAT, self.sample_count)?;
+            }
+            self.post.create_pipelines(&self.device, &mut self.pipelines)?;
+            self.oit.create_pipelines(&self.device, &mut self.pipelines)?;
+            Ok(figures)
+        });
+        match result {
+            Ok(figure_pipelines) => {
+                self.figure_pipelines = figure_pipelines;
+                log::info!("Reloaded the shaders");
+            }
+            Err(e) => log::error!("Could not reload the shaders, keeping the previous pipelines: {:#}", e),
+        }
+    }
+
+    /// Switches MSAA to `sample_count`, which has to be one of `sample_counts`.
+    fn set_sample_count(&mut self, sample_count: u32) {
+        let pipelines = FigurePipelines::new(&self.device, &mut self.pipelines, Texture::HDR_FORMAT, sample_count);
+        let pipelines = pipelines.and_then(|figures| {
+            self.ssao.create_pipelines(&self.device, &mut self.pipelines, sample_count)?;
+            if let Some(skybox) = &mut self.skybox {
+                skybox.create_pipelines(&self.device, &mut self.pipelines, Texture::HDR_FORMAT, sample_count)?;
+            }
+            Ok(figures)
+        });
+        let figure_pipelines = match pipelines {
+            Ok(pipelines) => pipelines,
+            Err(e) => {
+                log::error!("Could not build the pipelines for {}x MSAA: {:#}", sample_count, e);
+                return;
+            }
+        };
+        self.figure_pipelines = figure_pipelines;
+        self.sample_count = sample_count;
+        self.create_attachments();
+        log::info!("Rendering with {}x MSAA", sample_count);
+    }
+
+    /// Creates the depth texture and the multisampled target for the current size
+    /// and sample count.
+    fn create_attachments(&mut self) {
+        self.depth_texture =
+            Texture::create_depth_texture(&self.device, &self.config, self.sample_count, "depth_texture");
+        self.msaa_target = (self.sample_count > 1).then(|| {
+            let format = Texture::HDR_FORMAT;
+            Texture::create_msaa_target(&self.device, &self.config, format, self.sample_count, "msaa_target")
+        });
+        self.oit.resize(&self.device, &self.config, self.sample_count);
+    }
+
+    /// Enables the effect doing `mode`, and disables the others.
+    fn set_post_anti_aliasing(post: &mut PostProcessing, mode: PostAntiAliasing) {
+        post.set_enabled(Fxaa::NAME, mode == PostAntiAliasing::Fxaa);
+    }
+
+    fn update_fog(&mut self) {
+        self.fog.update(&self.queue);
+        let fog = &self.fog.settings;
+        match fog.mode {
+            FogMode::Off => log::info!("Fog disabled"),
+            FogMode::Linear => log::info!("Linear fog from {:.1} to {:.1}", fog.start, fog.end),
+            _ => log::info!("{:?} fog with density {:.3}", fog.mode, fog.density),
         }
     }
 
-    pub fn window(&self) -> &Window {
-        &self.window
+    fn update_tonemap(&mut self) {
+        let tonemap = &self.post.tonemap;
+        tonemap.update(&self.queue);
+        log::info!("Tonemapping with {:?} at exposure {:.2}", tonemap.tonemapper, tonemap.exposure);
+    }
+
+    pub fn window(&self) -> Option<&Window> {
+        self.window.as_ref()
+    }
+
+    /// Reads back the last rendered frame. Only possible when rendering headless,
+    /// the swapchain can't be copied from.
+    pub fn screenshot(&self) -> anyhow::Result<image::RgbaImage> {
+        match &self.target {
+            RenderTarget::Offscreen(texture) => texture.to_image(&self.device, &self.queue),
+            RenderTarget::Surface(_) => anyhow::bail!("Screenshots are only supported when rendering headless"),
+        }
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         self.projection.resize(new_size.width, new_size.height);
-        self.depth_texture = Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
         if new_size.width > 0 && new_size.height > 0 {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            match &mut self.target {
+                RenderTarget::Surface(surface) => surface.configure(&self.device, &self.config),
+                RenderTarget::Offscreen(texture) => {
+                    *texture = Texture::create_render_target(&self.device, &self.config, "offscreen_target")
+                }
+            }
+            self.create_attachments();
+            self.post.resize(&self.device, &self.config);
+            self.ssao.resize(&self.device, &self.config);
+            self.ssao.set_projection(&self.queue, self.projection.calc_matrix());
+            self.lighting_bind_group = self.lighting_layout.bind_group(
+                &self.device,
+                self.ssao.occlusion(),
+                &self.fog,
+                &self.environment,
+                &self.sun,
+            );
         }
     }
 
-    pub fn input(&mut self, event: &WindowEvent) -> bool {
+    pub fn input(&mut self, event: &InputEvent) -> bool {
 
         match event {
-            WindowEvent::KeyboardInput {
-                input:
-                KeyboardInput {
-                    state,
-                    virtual_keycode: Some(key),
-                    ..
-                },
-                ..
-            } => self.camera_controller.process_keyboard(*key, *state),
-            WindowEvent::MouseWheel { delta, .. } => {
+            InputEvent::MouseWheel { delta } => {
                 self.camera_controller.process_scroll(delta);
                 true
             }
-            WindowEvent::MouseInput {
-                button: MouseButton::Left,
-                state,
-                ..
-            } => {
-                self.mouse_pressed = *state == ElementState::Pressed;
+            InputEvent::MouseMotion { dx, dy } => {
+                if self.mouse_pressed {
+                    self.camera_controller.process_mouse(*dx, *dy);
+                }
+                self.mouse_pressed
+            }
+            _ => {
+                let mut handled = false;
+                for (action, state) in self.input_map.map_event(event) {
+                    handled |= self.process_action(action, state);
+                }
+                handled
+            }
+        }
+    }
+
+    fn process_action(&mut self, action: Action, state: ElementState) -> bool {
+        let pressed = state == ElementState::Pressed;
+        match action {
+            Action::Look => {
+                self.mouse_pressed = pressed;
+                true
+            }
+            Action::Quit => {
+                self.quit_requested |= pressed;
+                true
+            }
+            Action::ToggleWireframe => {
+                if pressed {
+                    if self.figure_pipelines.wireframe.is_some() {
+                        self.wireframe = !self.wireframe;
+                    } else {
+                        log::warn!("Wireframe rendering is not supported by this adapter");
+                    }
+                }
+                true
+            }
+            Action::CycleMsaa => {
+                if pressed {
+                    let next = self
+                        .sample_counts
+                        .iter()
+                        .position(|count| *count == self.sample_count)
+                        .map_or(0, |i| (i + 1) % self.sample_counts.len());
+                    self.set_sample_count(self.sample_counts[next]);
+                }
+                true
+            }
+            Action::CyclePostAntiAliasing => {
+                if pressed {
+                    self.post_anti_aliasing = self.post_anti_aliasing.next();
+                    Self::set_post_anti_aliasing(&mut self.post, self.post_anti_aliasing);
+                }
+                true
+            }
+            Action::ToggleBloom => {
+                if pressed {
+                    self.post.toggle(Bloom::NAME);
+                }
+                true
+            }
+            Action::ToggleSsao => {
+                if pressed {
+                    let ssao = &mut self.ssao.settings;
+                    ssao.enabled = !ssao.enabled;
+                    log::info!("SSAO {}", if ssao.enabled { "enabled" } else { "disabled" });
+                }
+                true
+            }
+            Action::CycleFog => {
+                if pressed {
+                    self.fog.settings.mode = self.fog.settings.mode.next();
+                    self.update_fog();
+                }
                 true
             }
-            _ => false
+            Action::CycleTransparency => {
+                if pressed {
+                    self.transparency = self.transparency.next();
+                    let weighted_blended = self.transparency == Transparency::WeightedBlended;
+                    self.oit.set_enabled(&self.device, &self.config, self.sample_count, weighted_blended);
+                    log::info!("{:?} transparency", self.transparency);
+                }
+                true
+            }
+            Action::ThickenFog | Action::ThinFog => {
+                if pressed {
+                    let factor = if action == Action::ThickenFog { 1.25 } else { 0.8 };
+                    self.fog.settings.thicken(factor);
+                    self.update_fog();
+                }
+                true
+            }
+            Action::CycleTonemapper => {
+                if pressed {
+                    self.post.tonemap.tonemapper = self.post.tonemap.tonemapper.next();
+                    self.update_tonemap();
+                }
+                true
+            }
+            Action::IncreaseExposure | Action::DecreaseExposure => {
+                if pressed {
+                    // Half a stop per press.
+                    let stops = if action == Action::IncreaseExposure { 0.5 } else { -0.5 };
+                    self.post.tonemap.exposure *= 2f32.powf(stops);
+                    self.update_tonemap();
+                }
+                true
+            }
+            _ => self.camera_controller.process_action(action, state),
         }
     }
 
+    /// Whether the next frames will look different even without further input.
+    pub fn is_animating(&self) -> bool {
+        self.camera_controller.is_active() || self.previous_camera != self.camera || self.assets.is_loading()
+    }
+
+    /// Advances the simulation by one fixed step.
     pub fn update(&mut self,  dt: instant::Duration) {
+        self.previous_camera = self.camera.clone();
         self.camera_controller.update_camera(&mut self.camera, dt);
-        self.camera_uniform.update_view_proj(&self.camera, &self.projection);
-        self.queue.write_buffer(&self.camera_buffer.buff, 0, bytemuck::cast_slice(&[self.camera_uniform]));
     }
 
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+    /// Loads the cubemap for `sky`, or returns `None` for a plain color.
+    async fn load_sky(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        pipelines: &mut PipelineCache,
+        sky: &Sky,
+    ) -> anyhow::Result<Option<Texture>> {
+        let load_image = |file_name: String| async move {
+            let bytes = resources::load_binary(&file_name).await?;
+            image::load_from_memory(&bytes).with_context(|| format!("Could not decode {:?}", file_name))
+        };
+        match sky {
+            Sky::Color(_) => Ok(None),
+            Sky::Cubemap(faces) => {
+                let mut images = Vec::with_capacity(faces.len());
+                for face in faces {
+                    images.push(load_image(face.clone()).await?.to_rgba8());
+                }
+                let images: [image::RgbaImage; 6] = images.try_into().expect("A cubemap has six faces");
+                Ok(Some(Texture::from_cube_faces(device, queue, &images, "sky")?))
+            }
+            Sky::Equirectangular(file_name) => {
+                let image = load_image(file_name.clone()).await?;
+                Ok(Some(Skybox::cube_from_equirectangular(device, queue, pipelines, &image, "sky")?))
+            }
+        }
+    }
+
+    /// Every mesh with the material it is drawn with. Until they are loaded,
+    /// models and materials are replaced by placeholders.
+    fn meshes(&self) -> impl Iterator<Item = (&model_obj::Mesh, &model_obj::Material)> {
+        let obj_model = self.assets.get_or_placeholder(&self.obj_model);
+        obj_model.meshes.iter().map(move |mesh| {
+            let material = match obj_model.materials.get(mesh.material) {
+                Some(material) => self.assets.get_or_placeholder(material),
+                None => self.assets.placeholder(),
+            };
+            (mesh, material)
+        })
+    }
+
+    /// The meshes whose material has `alpha_mode`.
+    fn meshes_with(&self, alpha_mode: AlphaMode) -> impl Iterator<Item = (&model_obj::Mesh, &model_obj::Material)> {
+        self.meshes().filter(move |(_, material)| material.factors.alpha_mode == alpha_mode)
+    }
+
+    /// Draws the meshes with their materials in group 0.
+    fn draw_models<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        meshes: impl IntoIterator<Item = (&'a model_obj::Mesh, &'a model_obj::Material)>,
+    ) {
+        for (mesh, material) in meshes {
+            render_pass.set_bind_group(0, &material.bind_group, &[]);
+            render_pass.draw_mesh_instanced(mesh, 0..self.instances.len() as u32);
+        }
+    }
+
+    /// Draws blended meshes once each. The vertex shader doesn't place the
+    /// instances, so drawing all of them would blend each mesh over itself.
+    fn draw_blended<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        meshes: impl IntoIterator<Item = (&'a model_obj::Mesh, &'a model_obj::Material)>,
+    ) {
+        for (mesh, material) in meshes {
+            render_pass.set_bind_group(0, &material.bind_group, &[]);
+            render_pass.draw_mesh_instanced(mesh, 0..1);
+        }
+    }
+
+    /// Renders a frame `alpha` of the way between the previous and the current update.
+    pub fn render(&mut self, alpha: f32) -> Result<(), wgpu::SurfaceError> {
+        self.hot_reload();
+        self.assets.update(&self.device, &self.queue, &self.figure_layout.bind_group_layout);
+
+        let camera = self.previous_camera.lerp(&self.camera, alpha);
+        self.camera_uniform.update_view_proj(&camera, &self.projection);
+        self.queue.write_buffer(&self.camera_buffer.buff, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+
+        let (output, view) = match &self.target {
+            RenderTarget::Surface(surface) => {
+                let output = surface.get_current_texture()?;
+                let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+                (Some(output), view)
+            }
+            RenderTarget::Offscreen(texture) => {
+                (None, texture.tex.create_view(&wgpu::TextureViewDescriptor::default()))
+            }
+        };
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
         });
+
+        // SSAO needs the depth of the whole scene before it is shaded. Wireframes
+        // shouldn't be hidden behind the prepass' faces.
+        let prepass = self.ssao.settings.enabled && !self.wireframe;
+        if prepass {
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Depth Prepass"),
+                    color_attachments: &[],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: &self.depth_texture.view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: wgpu::StoreOp::Store,
+                        }),
+                        stencil_ops: None,
+                    }),
+                    occlusion_query_set: None,
+                    timestamp_writes: None,
+                });
+                render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+                render_pass.set_pipeline(&self.figure_pipelines.depth.pipeline);
+                self.draw_models(&mut render_pass, self.meshes_with(AlphaMode::Opaque));
+                render_pass.set_pipeline(&self.figure_pipelines.depth_mask.pipeline);
+                self.draw_models(&mut render_pass, self.meshes_with(AlphaMode::Mask));
+            }
+            self.ssao.render(&self.device, &mut encoder, &self.depth_texture);
+        } else {
+            self.ssao.clear(&mut encoder);
+        }
+
         {
-            
+            // Distant geometry fades into the fog, so the background has to match it.
+            let [r, g, b] = match self.fog.settings.mode {
+                FogMode::Off => self.sky_color,
+                _ => self.fog.settings.color,
+            }
+            .map(f64::from);
+            let background = wgpu::Color { r, g, b, a: 1.0 };
+
+            // With MSAA the samples are drawn into their own target and only the
+            // resolved image is kept.
+            let (color_view, resolve_target, store) = match &self.msaa_target {
+                Some(msaa_target) => (&msaa_target.view, Some(&self.post.scene_target().view), wgpu::StoreOp::Discard),
+                None => (&self.post.scene_target().view, None, wgpu::StoreOp::Store),
+            };
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
+                    view: color_view,
+                    resolve_target,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color { 
-                            r: 0.5,
-                            g: 0.5,
-                            b: 1.0,
-                            a: 1.0
-                        }),
-                        store: wgpu::StoreOp::Store,
+                        load: wgpu::LoadOp::Clear(background),
+                        store,
                     },
                 })],
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &self.depth_texture.view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: if prepass { wgpu::LoadOp::Load } else { wgpu::LoadOp::Clear(1.0) },
                         store: wgpu::StoreOp::Store
                     }),
                     stencil_ops: None,
@@ -343,20 +906,67 @@ impl State {
                 timestamp_writes: None,
             });
 
-            render_pass.set_pipeline(&self.quad_pipeline.pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
             render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+            render_pass.set_bind_group(2, &self.lighting_bind_group, &[]);
             //render_pass.set_vertex_buffer(0, self.quad_model.vbuf().slice(..));
             //render_pass.set_vertex_buffer(1, self.instance_buffer.buff.slice(..));
             // render_pass.set_index_buffer(self.quad_model.ibuf().slice(..), wgpu::IndexFormat::Uint16);
             // render_pass.draw_indexed(0..self.quad_model.num_indices, 0, 0..1 as _);
 
-            render_pass.draw_mesh_instanced(&self.obj_model.meshes[0], 0..self.instances.len() as u32);
+            match &self.figure_pipelines.wireframe {
+                Some(wireframe_pipeline) if self.wireframe => {
+                    render_pass.set_pipeline(&wireframe_pipeline.pipeline);
+                    self.draw_models(&mut render_pass, self.meshes());
+                }
+                _ => {
+                    for alpha_mode in [AlphaMode::Opaque, AlphaMode::Mask] {
+                        render_pass.set_pipeline(&self.figure_pipelines.filled(alpha_mode).pipeline);
+                        self.draw_models(&mut render_pass, self.meshes_with(alpha_mode));
+                    }
+                }
+            }
+            if let Some(skybox) = &self.skybox {
+                skybox.draw(&mut render_pass);
+            }
+
+            // Blended meshes go over everything else, the farthest first so the
+            // nearer ones blend over them. Wireframes drew them already. They are
+            // drawn once, where the model puts them, so that is where they are
+            // sorted from.
+            if !self.wireframe && self.transparency == Transparency::Sorted {
+                let mut blended = self.meshes_with(AlphaMode::Blend).collect::<Vec<_>>();
+                let distance = |mesh: &model_obj::Mesh| camera.position.distance2(cgmath::Point3::from(mesh.center));
+                blended.sort_by(|(a, _), (b, _)| distance(b).total_cmp(&distance(a)));
+                render_pass.set_pipeline(&self.figure_pipelines.blend.pipeline);
+                self.draw_blended(&mut render_pass, blended);
+            }
+        }
+
+        // Without sorting, blended meshes are accumulated in their own pass and
+        // composited over the resolved scene.
+        let oit = !self.wireframe && self.oit.is_enabled() && self.meshes_with(AlphaMode::Blend).next().is_some();
+        if oit {
+            {
+                let mut render_pass = self.oit.begin_pass(&mut encoder, &self.depth_texture);
+                render_pass.set_bind_group(1, &self.camera_bind_group, &[]);
+                render_pass.set_bind_group(2, &self.lighting_bind_group, &[]);
+                render_pass.set_pipeline(&self.figure_pipelines.oit.pipeline);
+                self.draw_blended(&mut render_pass, self.meshes_with(AlphaMode::Blend));
+            }
+            self.oit.composite(&mut encoder, &self.post.scene_target().view);
         }
 
+        let mut ctx = PostContext {
+            device: &self.device,
+            encoder: &mut encoder,
+        };
+        self.post.render(&mut ctx, &view);
+
         // submit will accept anything that implements IntoIter
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+        if let Some(output) = output {
+            output.present();
+        }
         Ok(())
     }
 }
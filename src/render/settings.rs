@@ -0,0 +1,345 @@
+use std::str::FromStr;
+
+/// How finished frames are handed to the display.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PresentMode {
+    /// Wait for vertical blank, never tears. Supported everywhere.
+    #[default]
+    Vsync,
+    /// Replace the queued frame with the newest one, low latency without tearing.
+    Mailbox,
+    /// Present right away, may tear.
+    Immediate,
+}
+
+impl PresentMode {
+    fn to_wgpu(self) -> wgpu::PresentMode {
+        match self {
+            PresentMode::Vsync => wgpu::PresentMode::Fifo,
+            PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+            PresentMode::Immediate => wgpu::PresentMode::Immediate,
+        }
+    }
+
+    /// Returns this mode if the surface supports it, otherwise falls back to `Fifo`,
+    /// which every surface has to support.
+    pub fn select(self, supported: &[wgpu::PresentMode]) -> wgpu::PresentMode {
+        let mode = self.to_wgpu();
+        if supported.contains(&mode) {
+            mode
+        } else {
+            log::warn!(
+                "Present mode {:?} is not supported (available: {:?}), falling back to vsync",
+                self,
+                supported
+            );
+            wgpu::PresentMode::Fifo
+        }
+    }
+}
+
+impl FromStr for PresentMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "vsync" | "fifo" => Ok(PresentMode::Vsync),
+            "mailbox" => Ok(PresentMode::Mailbox),
+            "immediate" => Ok(PresentMode::Immediate),
+            _ => anyhow::bail!("unknown present mode {:?}, expected vsync, mailbox or immediate", s),
+        }
+    }
+}
+
+/// Curve that maps the HDR scene into the displayable range.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemapper {
+    /// Filmic, slightly contrasty. The usual choice.
+    #[default]
+    Aces,
+    /// Simple and neutral, but washes out bright colors.
+    Reinhard,
+    /// Desaturates highlights towards white instead of skewing their hue.
+    AgX,
+}
+
+impl Tonemapper {
+    /// The one after this, for cycling through them at runtime.
+    pub fn next(self) -> Self {
+        match self {
+            Tonemapper::Aces => Tonemapper::Reinhard,
+            Tonemapper::Reinhard => Tonemapper::AgX,
+            Tonemapper::AgX => Tonemapper::Aces,
+        }
+    }
+}
+
+impl FromStr for Tonemapper {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "aces" => Ok(Tonemapper::Aces),
+            "reinhard" => Ok(Tonemapper::Reinhard),
+            "agx" => Ok(Tonemapper::AgX),
+            _ => anyhow::bail!("unknown tonemapper {:?}, expected aces, reinhard or agx", s),
+        }
+    }
+}
+
+/// Anti-aliasing done on the tonemapped image, on top of or instead of MSAA.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PostAntiAliasing {
+    #[default]
+    Off,
+    /// FXAA, cheap and catches every edge, but blurs fine texture detail a bit.
+    Fxaa,
+}
+
+impl PostAntiAliasing {
+    /// The one after this, for cycling through them at runtime.
+    pub fn next(self) -> Self {
+        match self {
+            PostAntiAliasing::Off => PostAntiAliasing::Fxaa,
+            PostAntiAliasing::Fxaa => PostAntiAliasing::Off,
+        }
+    }
+}
+
+impl FromStr for PostAntiAliasing {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" | "none" => Ok(PostAntiAliasing::Off),
+            "fxaa" => Ok(PostAntiAliasing::Fxaa),
+            _ => anyhow::bail!("unknown anti-aliasing {:?}, expected off or fxaa", s),
+        }
+    }
+}
+
+/// How blended materials are layered over each other.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Transparency {
+    /// Drawn back to front, mesh by mesh. Exact as long as meshes don't
+    /// intersect or surround each other.
+    #[default]
+    Sorted,
+    /// Weighted blended order-independent transparency, see `render::oit`.
+    /// Needs no sorting and handles intersecting meshes, but only approximates
+    /// the order from the distance.
+    WeightedBlended,
+}
+
+impl Transparency {
+    /// The one after this, for cycling through them at runtime.
+    pub fn next(self) -> Self {
+        match self {
+            Transparency::Sorted => Transparency::WeightedBlended,
+            Transparency::WeightedBlended => Transparency::Sorted,
+        }
+    }
+}
+
+impl FromStr for Transparency {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "sorted" => Ok(Transparency::Sorted),
+            "oit" | "weighted-blended" => Ok(Transparency::WeightedBlended),
+            _ => anyhow::bail!("unknown transparency {:?}, expected sorted or oit", s),
+        }
+    }
+}
+
+/// Parameters of the bloom effect, see `post::bloom`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BloomSettings {
+    pub enabled: bool,
+    /// Brightness above which the scene starts to glow.
+    pub threshold: f32,
+    /// How far below the threshold the glow fades in.
+    pub knee: f32,
+    /// Strength of the glow added to the scene.
+    pub intensity: f32,
+    /// Spread of the blur between mips, in texels.
+    pub radius: f32,
+}
+
+impl Default for BloomSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            threshold: 1.0,
+            knee: 0.5,
+            intensity: 0.5,
+            radius: 1.0,
+        }
+    }
+}
+
+/// Parameters of the ambient occlusion pass, see `render::ssao`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SsaoSettings {
+    pub enabled: bool,
+    /// How far around a point, in world units, geometry occludes it.
+    pub radius: f32,
+    /// Depth difference below which samples don't count, against self-occlusion.
+    pub bias: f32,
+    /// Exponent the ambient light left is raised to, higher is darker.
+    pub intensity: f32,
+}
+
+impl Default for SsaoSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            radius: 0.5,
+            bias: 0.025,
+            intensity: 1.0,
+        }
+    }
+}
+
+/// How fog thickens with distance from the camera.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FogMode {
+    #[default]
+    Off,
+    /// Fades in between a start and an end distance.
+    Linear,
+    /// Thickens exponentially with distance.
+    Exponential,
+    /// Exponential, and thinning out with altitude, so it settles in valleys.
+    Height,
+}
+
+impl FogMode {
+    /// The one after this, for cycling through them at runtime.
+    pub fn next(self) -> Self {
+        match self {
+            FogMode::Off => FogMode::Linear,
+            FogMode::Linear => FogMode::Exponential,
+            FogMode::Exponential => FogMode::Height,
+            FogMode::Height => FogMode::Off,
+        }
+    }
+}
+
+impl FromStr for FogMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" | "none" => Ok(FogMode::Off),
+            "linear" => Ok(FogMode::Linear),
+            "exponential" | "exp" => Ok(FogMode::Exponential),
+            "height" => Ok(FogMode::Height),
+            _ => anyhow::bail!("unknown fog mode {:?}, expected off, linear, exponential or height", s),
+        }
+    }
+}
+
+/// Parameters of the fog, see `render::fog`. Which of them are used depends
+/// on the mode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FogSettings {
+    pub mode: FogMode,
+    /// Linear color the scene fades into, best matched to the sky.
+    pub color: [f32; 3],
+    /// Exponential and height fog: extinction per world unit.
+    pub density: f32,
+    /// Linear fog: distance the fog starts at.
+    pub start: f32,
+    /// Linear fog: distance past which everything is fog. At or below the far
+    /// plane, geometry fades out instead of popping out of view.
+    pub end: f32,
+    /// Height fog: altitude at which the fog has `density`.
+    pub height: f32,
+    /// Height fog: how quickly the fog thins out above `height`.
+    pub falloff: f32,
+}
+
+impl FogSettings {
+    /// Makes the fog `factor` times as thick, whatever the mode.
+    pub fn thicken(&mut self, factor: f32) {
+        self.density *= factor;
+        self.start /= factor;
+        self.end /= factor;
+    }
+}
+
+impl Default for FogSettings {
+    fn default() -> Self {
+        Self {
+            mode: FogMode::default(),
+            color: crate::scene::Sky::DEFAULT_COLOR,
+            density: 0.02,
+            start: 40.0,
+            end: 100.0,
+            height: 0.0,
+            falloff: 0.2,
+        }
+    }
+}
+
+/// Sample counts tried for MSAA, 1 being no multisampling.
+pub const SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+
+/// Returns `requested` if it is in `supported`, otherwise the highest supported
+/// count below it. 1 is always supported.
+pub fn select_sample_count(requested: u32, supported: &[u32]) -> u32 {
+    if supported.contains(&requested) {
+        return requested;
+    }
+    let fallback = supported.iter().copied().filter(|count| *count < requested).max().unwrap_or(1);
+    log::warn!(
+        "{}x MSAA is not supported (available: {:?}), falling back to {}x",
+        requested,
+        supported,
+        fallback
+    );
+    fallback
+}
+
+/// Options that decide how `State` sets up rendering.
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    /// Graphics APIs wgpu may pick an adapter from.
+    pub backends: wgpu::Backends,
+    pub present_mode: PresentMode,
+    /// Samples per pixel used for multisample anti-aliasing, 1 disables it.
+    pub sample_count: u32,
+    pub post_anti_aliasing: PostAntiAliasing,
+    pub tonemapper: Tonemapper,
+    /// Scene colors are multiplied by this before tonemapping.
+    pub exposure: f32,
+    pub bloom: BloomSettings,
+    pub ssao: SsaoSettings,
+    pub fog: FogSettings,
+    pub transparency: Transparency,
+    /// Render into an offscreen texture without a window, which needs no display.
+    pub headless: bool,
+    /// Reload shaders, textures and models when their files change.
+    pub hot_reload: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            backends: wgpu::Backends::all(),
+            present_mode: PresentMode::default(),
+            sample_count: 1,
+            post_anti_aliasing: PostAntiAliasing::default(),
+            tonemapper: Tonemapper::default(),
+            exposure: 1.0,
+            bloom: BloomSettings::default(),
+            ssao: SsaoSettings::default(),
+            fog: FogSettings::default(),
+            transparency: Transparency::default(),
+            headless: false,
+            hot_reload: cfg!(debug_assertions),
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use anyhow::anyhow;
+
+use crate::common::resources;
+
+use super::preprocessor::{preprocess, Preprocessed};
+
+/// Shaders built into the binary, used when the assets folder can't be found.
+const BUILTIN_SHADERS: &[(&str, &str)] = &[
+    ("shaders/shader.wgsl", include_str!("../../assets/shaders/shader.wgsl")),
+    ("shaders/camera.wgsl", include_str!("../../assets/shaders/camera.wgsl")),
+    ("shaders/fullscreen.wgsl", include_str!("../../assets/shaders/fullscreen.wgsl")),
+    ("shaders/tonemap.wgsl", include_str!("../../assets/shaders/tonemap.wgsl")),
+    ("shaders/bloom.wgsl", include_str!("../../assets/shaders/bloom.wgsl")),
+    ("shaders/ssao.wgsl", include_str!("../../assets/shaders/ssao.wgsl")),
+    ("shaders/fxaa.wgsl", include_str!("../../assets/shaders/fxaa.wgsl")),
+    ("shaders/cube.wgsl", include_str!("../../assets/shaders/cube.wgsl")),
+    ("shaders/equirectangular.wgsl", include_str!("../../assets/shaders/equirectangular.wgsl")),
+    ("shaders/sky.wgsl", include_str!("../../assets/shaders/sky.wgsl")),
+    ("shaders/ibl.wgsl", include_str!("../../assets/shaders/ibl.wgsl")),
+    ("shaders/pbr.wgsl", include_str!("../../assets/shaders/pbr.wgsl")),
+    ("shaders/oit.wgsl", include_str!("../../assets/shaders/oit.wgsl")),
+    ("shaders/fog.wgsl", include_str!("../../assets/shaders/fog.wgsl")),
+];
+
+/// WGSL source run through the preprocessor, see [`preprocess`] for the
+/// supported directives.
+///
+/// Features passed to [`Shader::load`] are defined before the first line, so one
+/// file can be built into several pipeline variants.
+pub struct Shader {
+    /// The file the shader was loaded from, also used as its label.
+    pub name: String,
+    preprocessed: Preprocessed,
+}
+
+impl Shader {
+    pub fn load(file_name: &str, features: &[&str]) -> anyhow::Result<Self> {
+        Ok(Self {
+            name: file_name.to_string(),
+            preprocessed: preprocess(file_name, features, &mut read)?,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.preprocessed.source
+    }
+
+    /// Every file that went into the shader, starting with the one it was loaded from.
+    pub fn files(&self) -> &[String] {
+        &self.preprocessed.files
+    }
+
+    /// Rewrites locations in a compiler message, which refer to the preprocessed
+    /// source, to the file and line they came from.
+    pub fn map_locations(&self, message: &str) -> String {
+        self.preprocessed.map_locations(&self.name, message)
+    }
+
+    /// Parses and validates the shader with naga, for reflection.
+    pub fn parse(&self) -> anyhow::Result<(naga::Module, naga::valid::ModuleInfo)> {
+        let module = naga::front::wgsl::parse_str(self.source())
+            .map_err(|e| anyhow!(self.map_locations(&e.emit_to_string_with_path(self.source(), &self.name))))?;
+        let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::empty())
+            .validate(&module)
+            .map_err(|e| anyhow!(self.map_locations(&e.emit_to_string_with_path(self.source(), &self.name))))?;
+        Ok((module, info))
+    }
+
+    /// Compiles the shader, returning validation errors instead of panicking.
+    pub fn create_module(&self, device: &wgpu::Device) -> anyhow::Result<wgpu::ShaderModule> {
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(&self.name),
+            source: wgpu::ShaderSource::Wgsl(self.source().into()),
+        });
+        match pollster::block_on(device.pop_error_scope()) {
+            None => Ok(module),
+            Some(e) => Err(anyhow!(self.map_locations(&e.to_string()))),
+        }
+    }
+}
+
+/// Reads a shader from the assets, or from the copies in the binary if the
+/// assets can't be found.
+fn read(file_name: &str) -> anyhow::Result<String> {
+    match pollster::block_on(resources::load_string(file_name)) {
+        Ok(source) => Ok(source),
+        Err(e) => match BUILTIN_SHADERS.iter().find(|(name, _)| *name == file_name) {
+            Some((_, source)) => {
+                log::warn!("Using the built in {}: {:?}", file_name, e);
+                Ok(source.to_string())
+            }
+            None => Err(e),
+        },
+    }
+}
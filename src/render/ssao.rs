@@ -0,0 +1,289 @@
+//! Screen-space ambient occlusion, computed from the depth of a prepass before
+//! the scene is shaded, so the scene shader can darken the ambient light with it.
+
+use std::f32::consts::TAU;
+use std::sync::Arc;
+
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::util::DeviceExt;
+
+use super::{
+    buffer::Buffer,
+    pipelines::cache::{PipelineCache, PipelineKey},
+    post::{fullscreen_key, fullscreen_pass, input_bind_group, INPUT_ENTRIES},
+    settings::SsaoSettings,
+    texture::Texture,
+};
+
+/// Matches KERNEL_SIZE in ssao.wgsl.
+const KERNEL_SIZE: usize = 16;
+/// Side of the tile of kernel rotations repeated over the screen.
+const NOISE_SIZE: u32 = 4;
+/// Ambient light left after occlusion, 1 being unoccluded.
+const OCCLUSION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SsaoUniform {
+    projection: [[f32; 4]; 4],
+    inverse_projection: [[f32; 4]; 4],
+    kernel: [[f32; 4]; KERNEL_SIZE],
+    radius: f32,
+    bias: f32,
+    intensity: f32,
+    _padding: f32,
+}
+
+impl SsaoUniform {
+    pub fn new(projection: Matrix4<f32>, settings: &SsaoSettings) -> Self {
+        let inverse_projection = projection.invert().unwrap_or_else(Matrix4::identity);
+        Self {
+            projection: projection.into(),
+            inverse_projection: inverse_projection.into(),
+            kernel: kernel(),
+            radius: settings.radius,
+            bias: settings.bias,
+            intensity: settings.intensity,
+            _padding: 0.0,
+        }
+    }
+}
+
+/// Offsets in the hemisphere around +z. They follow a Hammersley set, so they
+/// cover it evenly, weighted towards the normal like the light they stand for,
+/// and are packed closer to the center as they go, since nearby geometry
+/// occludes the most.
+fn kernel() -> [[f32; 4]; KERNEL_SIZE] {
+    std::array::from_fn(|i| {
+        let u = (i as f32 + 0.5) / KERNEL_SIZE as f32;
+        let v = (i as u32).reverse_bits() as f32 / 2f32.powi(32);
+        let r = u.sqrt();
+        let (sin, cos) = (v * TAU).sin_cos();
+        let t = i as f32 / KERNEL_SIZE as f32;
+        let scale = 0.1 + 0.9 * t * t;
+        [r * cos * scale, r * sin * scale, (1.0 - u).sqrt() * scale, 0.0]
+    })
+}
+
+/// Rotations around the normal for a tile of pixels, spread in the order of a
+/// Bayer matrix so neighbouring pixels get rotations far apart.
+fn noise() -> Vec<u8> {
+    const BAYER: [u32; 16] = [0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5];
+    BAYER
+        .iter()
+        .flat_map(|&rank| {
+            let (sin, cos) = (TAU * rank as f32 / 16.0).sin_cos();
+            [(cos * 127.0) as i8, (sin * 127.0) as i8, 0, 0]
+        })
+        .map(|snorm| snorm as u8)
+        .collect()
+}
+
+struct SsaoPipelines {
+    occlusion: Arc<wgpu::RenderPipeline>,
+    blur_horizontal: Arc<wgpu::RenderPipeline>,
+    blur_vertical: Arc<wgpu::RenderPipeline>,
+}
+
+/// Darkens creases and the places where objects touch. The occlusion is computed
+/// at full resolution into one target and blurred through the other back into
+/// it, where the scene shader reads it from.
+pub struct Ssao {
+    pub settings: SsaoSettings,
+    pipelines: SsaoPipelines,
+    depth_layout: Arc<wgpu::BindGroupLayout>,
+    input_layout: Arc<wgpu::BindGroupLayout>,
+    buffer: Buffer<SsaoUniform>,
+    bind_group: wgpu::BindGroup,
+    noise_bind_group: wgpu::BindGroup,
+    targets: [Texture; 2],
+}
+
+impl Ssao {
+    pub const SHADER: &'static str = "shaders/ssao.wgsl";
+
+    pub const ENTRIES: &'static [wgpu::BindGroupLayoutEntry] = &[
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        },
+    ];
+
+    /// The depth texture, read without a sampler.
+    fn depth_entries(sample_count: u32) -> [wgpu::BindGroupLayoutEntry; 1] {
+        [wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: sample_count > 1,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Depth,
+            },
+            count: None,
+        }]
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        cache: &mut PipelineCache,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+        projection: Matrix4<f32>,
+        settings: SsaoSettings,
+    ) -> anyhow::Result<Self> {
+        let buffer = Buffer::new(device, wgpu::BufferUsages::UNIFORM, &[SsaoUniform::new(projection, &settings)]);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &cache.bind_group_layout(device, Self::ENTRIES),
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.buff.as_entire_binding(),
+            }],
+            label: Some("ssao_bind_group"),
+        });
+
+        let noise = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("ssao_noise"),
+                size: wgpu::Extent3d {
+                    width: NOISE_SIZE,
+                    height: NOISE_SIZE,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8Snorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            &noise(),
+        );
+        let input_layout = cache.bind_group_layout(device, INPUT_ENTRIES);
+        let noise_sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        let noise_bind_group = input_bind_group(
+            device,
+            &input_layout,
+            &noise.create_view(&wgpu::TextureViewDescriptor::default()),
+            &noise_sampler,
+        );
+
+        Ok(Self {
+            settings,
+            pipelines: Self::pipelines(device, cache, sample_count)?,
+            depth_layout: cache.bind_group_layout(device, &Self::depth_entries(sample_count)),
+            input_layout,
+            buffer,
+            bind_group,
+            noise_bind_group,
+            targets: Self::create_targets(device, config),
+        })
+    }
+
+    fn pipelines(device: &wgpu::Device, cache: &mut PipelineCache, sample_count: u32) -> anyhow::Result<SsaoPipelines> {
+        let key = |fragment_entry: &str| -> PipelineKey {
+            let key = fullscreen_key(&format!("SSAO {}", fragment_entry), Self::SHADER, OCCLUSION_FORMAT, None)
+                .entry_points("vs_main", Some(fragment_entry))
+                .bind_group(&Self::depth_entries(sample_count))
+                .bind_group(Self::ENTRIES)
+                .bind_group(INPUT_ENTRIES);
+            if sample_count > 1 {
+                key.feature("MULTISAMPLED")
+            } else {
+                key
+            }
+        };
+
+        Ok(SsaoPipelines {
+            occlusion: cache.get(device, &key("fs_occlusion"))?,
+            blur_horizontal: cache.get(device, &key("fs_blur_horizontal"))?,
+            blur_vertical: cache.get(device, &key("fs_blur_vertical"))?,
+        })
+    }
+
+    fn create_targets(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> [Texture; 2] {
+        [0, 1].map(|i| Texture::create_color_target(device, config, OCCLUSION_FORMAT, &format!("ssao_target_{}", i)))
+    }
+
+    /// Builds the pipelines again, for new shaders or a depth texture with a
+    /// different sample count.
+    pub fn create_pipelines(
+        &mut self,
+        device: &wgpu::Device,
+        cache: &mut PipelineCache,
+        sample_count: u32,
+    ) -> anyhow::Result<()> {
+        self.pipelines = Self::pipelines(device, cache, sample_count)?;
+        self.depth_layout = cache.bind_group_layout(device, &Self::depth_entries(sample_count));
+        Ok(())
+    }
+
+    /// The occlusion targets follow the surface size. The lighting bind group has
+    /// to be created again afterwards.
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.targets = Self::create_targets(device, config);
+    }
+
+    /// Uploads a new projection, e.g. after the aspect ratio changed.
+    pub fn set_projection(&self, queue: &wgpu::Queue, projection: Matrix4<f32>) {
+        let uniform = SsaoUniform::new(projection, &self.settings);
+        queue.write_buffer(&self.buffer.buff, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    /// Where the blurred occlusion ends up, bound to the scene pass.
+    pub fn occlusion(&self) -> &wgpu::TextureView {
+        &self.targets[0].view
+    }
+
+    /// Computes the occlusion from `depth`, which has to hold the depth of the
+    /// whole scene already.
+    pub fn render(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, depth: &Texture) {
+        let depth = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.depth_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&depth.view),
+            }],
+            label: Some("ssao_depth_bind_group"),
+        });
+        let bind = |texture: &Texture| input_bind_group(device, &self.input_layout, &texture.view, &texture.sampler);
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::WHITE);
+        let pipelines = &self.pipelines;
+
+        let bind_groups = [&depth, &self.bind_group, &self.noise_bind_group];
+        fullscreen_pass(encoder, "SSAO Pass", &pipelines.occlusion, &bind_groups, &self.targets[0].view, clear);
+
+        let input = bind(&self.targets[0]);
+        let bind_groups = [&depth, &self.bind_group, &input];
+        fullscreen_pass(encoder, "SSAO Blur", &pipelines.blur_horizontal, &bind_groups, &self.targets[1].view, clear);
+
+        let input = bind(&self.targets[1]);
+        let bind_groups = [&depth, &self.bind_group, &input];
+        fullscreen_pass(encoder, "SSAO Blur", &pipelines.blur_vertical, &bind_groups, &self.targets[0].view, clear);
+    }
+
+    /// Leaves the scene unoccluded, for frames without a depth prepass.
+    pub fn clear(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("SSAO Clear"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: self.occlusion(),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+    }
+}
@@ -0,0 +1,48 @@
+//! The directional light the scene shader shades surfaces with.
+
+use cgmath::InnerSpace;
+
+use super::buffer::Buffer;
+use crate::scene;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SunUniform {
+    /// Normalized, pointing the way the light travels.
+    direction: [f32; 4],
+    color: [f32; 4],
+}
+
+impl From<&scene::Sun> for SunUniform {
+    fn from(sun: &scene::Sun) -> Self {
+        let direction = cgmath::Vector3::from(sun.direction);
+        // A zero direction can't light anything, it is as good as no sun.
+        let (direction, color) = if direction.magnitude2() > 0.0 {
+            (direction.normalize(), sun.color)
+        } else {
+            (-cgmath::Vector3::unit_y(), [0.0; 3])
+        };
+        let [r, g, b] = color;
+        Self {
+            direction: direction.extend(0.0).into(),
+            color: [r, g, b, 1.0],
+        }
+    }
+}
+
+/// The sun and the uniform buffer the scene shader reads it from.
+pub struct Sun {
+    buffer: Buffer<SunUniform>,
+}
+
+impl Sun {
+    pub fn new(device: &wgpu::Device, sun: &scene::Sun) -> Self {
+        Self {
+            buffer: Buffer::new(device, wgpu::BufferUsages::UNIFORM, &[SunUniform::from(sun)]),
+        }
+    }
+
+    pub fn binding(&self) -> wgpu::BindingResource<'_> {
+        self.buffer.buff.as_entire_binding()
+    }
+}
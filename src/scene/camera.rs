@@ -3,6 +3,10 @@ use winit::event::*;
 use winit::dpi::PhysicalPosition;
 use instant::Duration;
 use std::f32::consts::FRAC_PI_2;
+use std::sync::Arc;
+
+use crate::input::bindings::Action;
+use crate::render::pipelines::cache::PipelineCache;
 
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
@@ -15,7 +19,7 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
 
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Camera {
     pub position: Point3<f32>,
     yaw: Rad<f32>,
@@ -54,6 +58,15 @@ impl Camera {
             Vector3::unit_y(),
         )
     }
+
+    /// Blends between two camera states, `alpha` 0 being `self` and 1 `other`.
+    pub fn lerp(&self, other: &Camera, alpha: f32) -> Camera {
+        Camera {
+            position: self.position + (other.position - self.position) * alpha,
+            yaw: self.yaw + (other.yaw - self.yaw) * alpha,
+            pitch: self.pitch + (other.pitch - self.pitch) * alpha,
+        }
+    }
 }
 
 
@@ -123,30 +136,30 @@ impl CameraController {
         }
     }
 
-    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool{
+    pub fn process_action(&mut self, action: Action, state: ElementState) -> bool {
         let amount = if state == ElementState::Pressed { 1.0 } else { 0.0 };
-        match key {
-            VirtualKeyCode::W | VirtualKeyCode::Up => {
+        match action {
+            Action::MoveForward => {
                 self.amount_forward = amount;
                 true
             }
-            VirtualKeyCode::S | VirtualKeyCode::Down => {
+            Action::MoveBackward => {
                 self.amount_backward = amount;
                 true
             }
-            VirtualKeyCode::A | VirtualKeyCode::Left => {
+            Action::MoveLeft => {
                 self.amount_left = amount;
                 true
             }
-            VirtualKeyCode::D | VirtualKeyCode::Right => {
+            Action::MoveRight => {
                 self.amount_right = amount;
                 true
             }
-            VirtualKeyCode::Space => {
+            Action::Ascend => {
                 self.amount_up = amount;
                 true
             }
-            VirtualKeyCode::LShift => {
+            Action::Descend => {
                 self.amount_down = amount;
                 true
             }
@@ -154,6 +167,23 @@ impl CameraController {
         }
     }
 
+    /// Whether the controller will move the camera on the next update.
+    pub fn is_active(&self) -> bool {
+        [
+            self.amount_left,
+            self.amount_right,
+            self.amount_forward,
+            self.amount_backward,
+            self.amount_up,
+            self.amount_down,
+            self.rotate_horizontal,
+            self.rotate_vertical,
+            self.scroll,
+        ]
+        .iter()
+        .any(|amount| *amount != 0.0)
+    }
+
     pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
         self.rotate_horizontal = mouse_dx as f32;
         self.rotate_vertical = mouse_dy as f32;
@@ -215,28 +245,27 @@ impl CameraController {
 
 
 pub struct CameraLayout {
-    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group_layout: Arc<wgpu::BindGroupLayout>,
 }
 
 
 impl CameraLayout {
-    pub fn new(device: &wgpu::Device) -> Self {
+    pub const ENTRIES: &'static [wgpu::BindGroupLayoutEntry] = &[
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    ];
+
+    pub fn new(device: &wgpu::Device, cache: &mut PipelineCache) -> Self {
         Self {
-            bind_group_layout: device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    }
-                ],
-                label: Some("camera_bind_group_layout"),
-            })
+            bind_group_layout: cache.bind_group_layout(device, Self::ENTRIES),
         }
     }
 }
@@ -245,17 +274,26 @@ impl CameraLayout {
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
     view_proj: [[f32; 4]; 4],
+    /// From clip space back to world space.
+    inverse_view_proj: [[f32; 4]; 4],
+    /// World space position, w is unused.
+    view_position: [f32; 4],
 }
 
 impl CameraUniform {
     pub fn new() -> Self {
         Self {
             view_proj: cgmath::Matrix4::identity().into(),
+            inverse_view_proj: cgmath::Matrix4::identity().into(),
+            view_position: [0.0; 4],
         }
     }
 
     // UPDATED!
     pub fn update_view_proj(&mut self, camera: &Camera, projection: &Projection) {
-        self.view_proj = (projection.calc_matrix() * camera.calc_matrix()).into()
+        let view_proj = projection.calc_matrix() * camera.calc_matrix();
+        self.view_proj = view_proj.into();
+        self.inverse_view_proj = view_proj.invert().unwrap_or_else(Matrix4::identity).into();
+        self.view_position = camera.position.to_homogeneous().into();
     }
 }
\ No newline at end of file
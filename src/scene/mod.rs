@@ -1 +1,66 @@
-pub mod camera;
\ No newline at end of file
+pub mod camera;
+
+/// What is shown behind the scene.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sky {
+    /// A flat linear color.
+    Color([f32; 3]),
+    /// A cubemap from six images, in the order +X, -X, +Y, -Y, +Z, -Z.
+    Cubemap([String; 6]),
+    /// An equirectangular panorama, usually HDR, turned into a cubemap on load.
+    Equirectangular(String),
+}
+
+impl Sky {
+    /// Also shown when a sky fails to load.
+    pub const DEFAULT_COLOR: [f32; 3] = [0.5, 0.5, 1.0];
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Sky::Color(Self::DEFAULT_COLOR)
+    }
+}
+
+/// A directional light shining on the whole scene from far away.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sun {
+    /// Direction the light travels in, doesn't need to be normalized.
+    pub direction: [f32; 3],
+    /// Linear color times intensity, black turns the sun off.
+    pub color: [f32; 3],
+}
+
+impl Default for Sun {
+    fn default() -> Self {
+        Self {
+            direction: [-0.4, -1.0, -0.6],
+            color: [3.0; 3],
+        }
+    }
+}
+
+/// What to show and where to look at it from when the application starts.
+#[derive(Debug, Clone)]
+pub struct SceneSettings {
+    /// Model to load, either a path or a file name inside the assets folder.
+    pub model: String,
+    pub camera_position: cgmath::Point3<f32>,
+    pub camera_yaw: cgmath::Deg<f32>,
+    pub camera_pitch: cgmath::Deg<f32>,
+    pub sky: Sky,
+    pub sun: Sun,
+}
+
+impl Default for SceneSettings {
+    fn default() -> Self {
+        Self {
+            model: "cube.obj".to_string(),
+            camera_position: cgmath::Point3::new(0.0, 5.0, 10.0),
+            camera_yaw: cgmath::Deg(-90.0),
+            camera_pitch: cgmath::Deg(-20.0),
+            sky: Sky::default(),
+            sun: Sun::default(),
+        }
+    }
+}